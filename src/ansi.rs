@@ -0,0 +1,88 @@
+// ============================================================================
+// ANSI ESCAPE HANDLING (notebook tracebacks)
+// ============================================================================
+
+/// Remove ANSI escape sequences, leaving plain text.
+pub fn strip_ansi_codes(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip until we hit a letter (end of ANSI sequence)
+            while let Some(&next) = chars.peek() {
+                chars.next();
+                if next.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Convert ANSI escape codes to HTML spans with colors.
+/// Text is HTML-escaped; unknown codes and resets close the current span.
+pub fn ansi_to_html(s: &str) -> String {
+    let mut result = String::new();
+    let mut in_span = false;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Parse ANSI sequence
+            if chars.peek() == Some(&'[') {
+                chars.next(); // consume '['
+                let mut code = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_digit() || next == ';' {
+                        code.push(chars.next().unwrap());
+                    } else {
+                        chars.next(); // consume the letter (usually 'm')
+                        break;
+                    }
+                }
+
+                // Close current span if open
+                if in_span {
+                    result.push_str("</span>");
+                    in_span = false;
+                }
+
+                // Map ANSI code to color
+                let color = match code.as_str() {
+                    "31" | "0;31" | "1;31" => Some("#e06c75"), // red
+                    "32" | "0;32" | "1;32" => Some("#98c379"), // green
+                    "33" | "0;33" | "1;33" => Some("#e5c07b"), // yellow
+                    "34" | "0;34" | "1;34" => Some("#61afef"), // blue
+                    "35" | "0;35" | "1;35" => Some("#c678dd"), // magenta
+                    "36" | "0;36" | "1;36" => Some("#56b6c2"), // cyan
+                    "37" | "0;37" | "1;37" => Some("#abb2bf"), // white
+                    "38;5;160" | "38;5;196" => Some("#e06c75"), // extended red
+                    "38;5;28" | "38;5;34" => Some("#98c379"), // extended green
+                    _ => None, // reset or unknown
+                };
+
+                if let Some(col) = color {
+                    result.push_str(&format!("<span style=\"color:{}\">", col));
+                    in_span = true;
+                }
+            }
+        } else {
+            // Escape HTML characters
+            match c {
+                '<' => result.push_str("&lt;"),
+                '>' => result.push_str("&gt;"),
+                '&' => result.push_str("&amp;"),
+                _ => result.push(c),
+            }
+        }
+    }
+
+    // Close any open span
+    if in_span {
+        result.push_str("</span>");
+    }
+    result
+}
@@ -0,0 +1,1190 @@
+// ============================================================================
+// DESKTOP VIEWER (the `app` feature): windows, single instance and `marrow ctl`
+// ============================================================================
+
+use marrow::page::{build_full_html_markdown, build_full_html_notebook, build_toc_html};
+use marrow::{expand_includes, notebook_to_markdown, render_markdown, render_notebook, slugify, source_view_html, Notebook, RenderOptions, Rendered, Settings, TruncatedOutputs};
+use notify::event::{EventKind, ModifyKind};
+use notify::{Config as NotifyConfig, Event as NotifyEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tao::{
+    dpi::{LogicalSize, PhysicalPosition},
+    event::{Event as TaoEvent, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget},
+    window::{Window, WindowBuilder, WindowId},
+};
+use wry::{WebView, WebViewBuilder};
+
+use crate::get_settings_path;
+
+#[derive(Debug)]
+enum UserEvent {
+    CloseWindow(WindowId),
+    QuitApp,
+    RequestOutputLines {
+        window_id: WindowId,
+        cell_idx: usize,
+        output_idx: usize,
+        amount: String,
+    },
+    FileChanged(WindowId),
+    /// More markdown arrived on stdin for a `marrow -` window
+    StdinChunk(WindowId, String),
+    /// The page finished loading and `reloadContent` can be called
+    PageReady(WindowId),
+    /// Paths handed over by another `marrow` invocation
+    OpenFiles(OpenRequest),
+    /// `marrow ctl ... reload`
+    ReloadWindows(ControlTarget),
+    /// `marrow ctl ... scroll-to <heading|line>`
+    ScrollWindows(ControlTarget, ScrollTarget),
+    /// `marrow ctl ... theme <dark|light>`
+    SetTheme(ControlTarget, String),
+    /// `marrow ctl ... close`
+    CloseWindows(ControlTarget),
+    /// A renderer command finished in the background
+    BlocksRendered,
+}
+
+/// Windows addressed by `marrow ctl` (`--path`, or all windows when `None`), and where to
+/// report how many of them the command was applied to
+#[derive(Debug)]
+struct ControlTarget {
+    path: Option<PathBuf>,
+    reply: mpsc::Sender<usize>,
+}
+
+/// Where a window scrolls once its page has loaded (`--line`, `--heading`, `--find`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ScrollTarget {
+    /// 1-based source line
+    Line(usize),
+    /// Heading text (or slug)
+    Heading(String),
+    /// Search term, highlighted with the search bar open
+    Find(String),
+}
+
+impl ScrollTarget {
+    /// Argument for the JS `scrollToTarget()`
+    fn to_js(&self) -> String {
+        let value = match self {
+            ScrollTarget::Line(line) => serde_json::json!({ "line": line }),
+            ScrollTarget::Heading(text) => serde_json::json!({ "heading": text, "slug": slugify(text) }),
+            ScrollTarget::Find(query) => serde_json::json!({ "find": query }),
+        };
+        value.to_string()
+    }
+}
+
+/// What later invocations send to the running instance (as JSON)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum InstanceMessage {
+    Open(OpenRequest),
+    Control {
+        path: Option<PathBuf>,
+        command: ControlCommand,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ControlCommand {
+    Reload,
+    ScrollTo(ScrollTarget),
+    Theme(String),
+    Close,
+}
+
+/// Files to open, as given on the command line (also sent to a running instance)
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct OpenRequest {
+    paths: Vec<PathBuf>,
+    scroll_to: Option<ScrollTarget>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+struct AllSettings {
+    #[serde(default)]
+    default: Settings,
+    #[serde(default)]
+    extensions: HashMap<String, Settings>,
+}
+
+impl AllSettings {
+    fn get_for_extension(&self, ext: &str) -> &Settings {
+        self.extensions.get(ext).unwrap_or(&self.default)
+    }
+
+    fn set_for_extension(&mut self, ext: &str, settings: Settings) {
+        self.extensions.insert(ext.to_string(), settings);
+    }
+}
+
+
+// ============================================================================
+// SETTINGS PERSISTENCE
+// ============================================================================
+
+
+fn load_settings() -> AllSettings {
+    get_settings_path()
+        .and_then(|path| std::fs::read_to_string(&path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+
+fn save_settings(settings: &AllSettings) {
+    if let Some(path) = get_settings_path() {
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_string_pretty(settings) {
+            let _ = std::fs::write(&path, json);
+        }
+    }
+}
+
+// ============================================================================
+// SINGLE INSTANCE
+// ============================================================================
+
+// Later invocations hand their paths to the first process over a per-user Unix socket,
+// so there is one event loop (and one writer of the settings file) per user.
+
+#[cfg(unix)]
+fn instance_socket_path() -> Option<PathBuf> {
+    let dirs = directories::ProjectDirs::from("com", "marrow", "app")?;
+    let dir = dirs.runtime_dir().unwrap_or(dirs.cache_dir());
+    Some(dir.join("instance.sock"))
+}
+
+/// Send a message to the running instance and return its reply (empty for `Open`).
+#[cfg(unix)]
+fn send_to_instance(mut stream: UnixStream, message: &InstanceMessage) -> std::io::Result<String> {
+    serde_json::to_writer(&mut stream, message)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// Accept messages from later invocations and dispatch them to the event loop.
+/// Returns the socket path to remove on exit.
+#[cfg(unix)]
+fn listen_for_instances(proxy: EventLoopProxy<UserEvent>) -> Option<PathBuf> {
+    let path = instance_socket_path()?;
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    // Only a socket nobody answers on (left by a crashed instance) may be replaced; if
+    // another instance started since we checked, leave its socket alone
+    match UnixStream::connect(&path) {
+        Ok(_) => return None,
+        Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => {
+            let _ = std::fs::remove_file(&path);
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(&path).ok()?;
+
+    // Each connection gets its own thread, so a client that never finishes its message
+    // can't hold up the others
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let proxy = proxy.clone();
+            std::thread::spawn(move || handle_instance_connection(stream, &proxy));
+        }
+    });
+    Some(path)
+}
+
+/// Read one message from a later invocation and pass it on to the event loop.
+#[cfg(unix)]
+fn handle_instance_connection(mut stream: UnixStream, proxy: &EventLoopProxy<UserEvent>) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut message = String::new();
+    if stream.read_to_string(&mut message).is_err() {
+        return;
+    }
+    let event = match serde_json::from_str::<InstanceMessage>(&message) {
+        Ok(InstanceMessage::Open(request)) => UserEvent::OpenFiles(request),
+        Ok(InstanceMessage::Control { path, command }) => {
+            let (reply, applied) = mpsc::channel();
+            let target = ControlTarget { path, reply };
+            let event = match command {
+                ControlCommand::Reload => UserEvent::ReloadWindows(target),
+                ControlCommand::ScrollTo(scroll_to) => UserEvent::ScrollWindows(target, scroll_to),
+                ControlCommand::Theme(theme) => UserEvent::SetTheme(target, theme),
+                ControlCommand::Close => UserEvent::CloseWindows(target),
+            };
+            if proxy.send_event(event).is_err() {
+                return;
+            }
+            // Tell `marrow ctl` how many windows matched so it can fail on zero
+            let count = applied.recv_timeout(Duration::from_secs(5)).unwrap_or(0);
+            let _ = writeln!(stream, "{}", count);
+            return;
+        }
+        Err(_) => return,
+    };
+    let _ = proxy.send_event(event);
+}
+
+// ============================================================================
+// WINDOW MANAGEMENT
+// ============================================================================
+
+struct AppWindow {
+    window: Arc<Window>,
+    webview: WebView,
+    file_path: Option<PathBuf>,
+    truncated_outputs: TruncatedOutputs,
+    #[allow(dead_code)]
+    watcher: Option<RecommendedWatcher>,
+    /// The file and the files it includes, as currently watched
+    watched_files: Vec<PathBuf>,
+    last_reload: Instant,
+    /// Markdown received so far, for windows showing stdin
+    stream: Option<String>,
+    page_ready: bool,
+    /// The last render left blocks for their renderer to finish in the background
+    pending_blocks: bool,
+    /// A block finished rendering before the page loaded, so it needs to be redrawn then
+    rerender_when_ready: bool,
+}
+
+fn setup_file_watcher(
+    paths: &[PathBuf],
+    window_id: WindowId,
+    proxy: EventLoopProxy<UserEvent>,
+) -> Option<RecommendedWatcher> {
+    let target_paths = paths.to_vec();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |res: Result<NotifyEvent, _>| {
+            if let Ok(event) = res {
+                // Notes added, removed or renamed change what wiki-links resolve to
+                if event.kind.is_create() || event.kind.is_remove() || matches!(event.kind, EventKind::Modify(ModifyKind::Name(_))) {
+                    marrow::markdown::forget_vault_files();
+                }
+                // React to modify or create events (editors often save by delete+create)
+                if event.kind.is_modify() || event.kind.is_create() {
+                    // Check if any of the affected paths match the file or one it includes
+                    let is_our_file = event.paths.iter().any(|p| target_paths.contains(p));
+                    if is_our_file {
+                        let _ = proxy.send_event(UserEvent::FileChanged(window_id));
+                    }
+                }
+            }
+        },
+        NotifyConfig::default().with_poll_interval(Duration::from_millis(290)),
+    ).ok()?;
+
+    // Watch the files' parent directories (more reliable than watching files directly)
+    // This handles atomic saves where editors delete + rename temp file
+    let mut parents: Vec<&Path> = paths.iter().filter_map(|p| p.parent()).collect();
+    parents.sort();
+    parents.dedup();
+    for parent in parents {
+        watcher.watch(parent, RecursiveMode::NonRecursive).ok()?;
+    }
+
+    Some(watcher)
+}
+
+/// A markdown file and every file it includes (notebooks are just themselves)
+fn watched_files(path: &Path) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    let is_notebook = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ipynb"));
+    if let (false, Ok(content)) = (is_notebook, std::fs::read_to_string(path)) {
+        files.extend(expand_includes(&content, path.parent(), Some(path)).files);
+    }
+    files
+}
+
+/// Generate JavaScript call to reload content in the WebView
+fn reload_file_content(app_window: &mut AppWindow) -> Option<String> {
+    let path = app_window.file_path.as_ref()?;
+    let options = RenderOptions {
+        base_dir: path.parent().map(Path::to_path_buf),
+        file: Some(path.clone()),
+        truncate_outputs: true,
+    };
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("md")
+        .to_lowercase();
+
+    if extension == "ipynb" {
+        // Read raw JSON for notebooks (don't use load_file which converts to markdown)
+        let content = std::fs::read_to_string(path).ok()?;
+        let notebook = serde_json::from_str::<Notebook>(&content).ok()?;
+        let rendered = render_notebook(&notebook, &options);
+        app_window.pending_blocks = rendered.pending_blocks;
+        let toc_html = build_toc_html(&rendered.toc);
+        Some(format!(
+            "reloadContent({}, {}, true)",
+            serde_json::to_string(&rendered.html).unwrap_or_default(),
+            serde_json::to_string(&toc_html).unwrap_or_default(),
+        ))
+    } else {
+        // For markdown, use load_file
+        let (content, _filename) = load_file(Some(path));
+        let rendered = render_markdown(&content, &options);
+        app_window.pending_blocks = rendered.pending_blocks;
+        Some(markdown_reload_script(&rendered))
+    }
+}
+
+/// JavaScript call that swaps in freshly rendered markdown
+fn markdown_reload_script(rendered: &Rendered) -> String {
+    let toc_html = build_toc_html(&rendered.toc);
+    // Terminal view shows the source with includes expanded, as data-lines are
+    let terminal_content = source_view_html(&rendered.source_map.lines().join("\n"), &rendered.toc);
+    format!(
+        "reloadContent({}, {}, false, {})",
+        serde_json::to_string(&rendered.html).unwrap_or_default(),
+        serde_json::to_string(&toc_html).unwrap_or_default(),
+        serde_json::to_string(&terminal_content).unwrap_or_default(),
+    )
+}
+
+/// Re-render a stdin window with everything received so far (once its page has loaded)
+fn refresh_stream(app_window: &mut AppWindow) {
+    let Some(content) = app_window.stream.as_deref() else { return };
+    if !app_window.page_ready || content.is_empty() {
+        return;
+    }
+    let options = RenderOptions {
+        base_dir: std::env::current_dir().ok(),
+        file: None,
+        truncate_outputs: true,
+    };
+    let rendered = render_markdown(content, &options);
+    app_window.pending_blocks = rendered.pending_blocks;
+    let _ = app_window.webview.evaluate_script(&markdown_reload_script(&rendered));
+    app_window.window.set_title(&window_title(&rendered, "stdin"));
+}
+
+/// Read stdin on a background thread and forward it to the window as it arrives
+fn spawn_stdin_reader(window_id: WindowId, proxy: EventLoopProxy<UserEvent>) {
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin().lock();
+        let mut buf = [0u8; 8192];
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => pending.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+            // Hold back a multi-byte character split across reads
+            let complete = match std::str::from_utf8(&pending) {
+                Ok(_) => pending.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => pending.len(),
+            };
+            if complete == 0 {
+                continue;
+            }
+            let chunk = String::from_utf8_lossy(&pending[..complete]).into_owned();
+            pending.drain(..complete);
+            if proxy.send_event(UserEvent::StdinChunk(window_id, chunk)).is_err() {
+                return;
+            }
+        }
+        if !pending.is_empty() {
+            let chunk = String::from_utf8_lossy(&pending).into_owned();
+            let _ = proxy.send_event(UserEvent::StdinChunk(window_id, chunk));
+        }
+    });
+}
+
+/// Window title: "Title · filename · Marrow 🦴", where the title comes from the front
+/// matter or else the first heading
+fn window_title(rendered: &Rendered, filename: &str) -> String {
+    let heading = rendered.front_matter.as_ref()
+        .and_then(|front_matter| front_matter.title())
+        .or_else(|| rendered.toc.first().map(|entry| entry.text.as_str()))
+        .map(|title| truncate_end(title, 20));
+    let short_filename = truncate_middle(filename, 20);
+    match heading {
+        Some(heading) => format!("{} · {} · Marrow 🦴", heading, short_filename),
+        None => format!("{} · Marrow 🦴", short_filename),
+    }
+}
+
+fn truncate_end(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        format!("{}…", s.chars().take(max - 1).collect::<String>())
+    }
+}
+
+fn truncate_middle(s: &str, max: usize) -> String {
+    let len = s.chars().count();
+    if len <= max {
+        s.to_string()
+    } else {
+        let keep = max - 1; // -1 for the ellipsis
+        let left = keep / 2;
+        let right = keep - left;
+        let left_part: String = s.chars().take(left).collect();
+        let right_part: String = s.chars().skip(len - right).collect();
+        format!("{}…{}", left_part, right_part)
+    }
+}
+
+fn calculate_cascade_position(existing: &HashMap<WindowId, AppWindow>) -> Option<PhysicalPosition<i32>> {
+    if existing.is_empty() {
+        return None;
+    }
+
+    // Find the window furthest down-right and offset from it
+    let mut max_offset: i32 = 0;
+    let mut base_pos: Option<(i32, i32)> = None;
+
+    for app_window in existing.values() {
+        if let Ok(pos) = app_window.window.outer_position() {
+            let offset = pos.x + pos.y;
+            if offset >= max_offset {
+                max_offset = offset;
+                base_pos = Some((pos.x, pos.y));
+            }
+        }
+    }
+
+    // Offset right and down (50 physical pixels each)
+    base_pos.map(|(x, y)| PhysicalPosition::new(x + 50, y + 50))
+}
+
+/// Windows a `marrow ctl` command applies to
+fn control_targets(windows: &HashMap<WindowId, AppWindow>, target: &ControlTarget) -> Vec<WindowId> {
+    match &target.path {
+        Some(path) => find_window_for_path(windows, path).into_iter().collect(),
+        None => windows.keys().copied().collect(),
+    }
+}
+
+fn find_window_for_path(windows: &HashMap<WindowId, AppWindow>, path: &Path) -> Option<WindowId> {
+    for (id, app_window) in windows {
+        if let Some(ref existing_path) = app_window.file_path {
+            if existing_path == path {
+                return Some(*id);
+            }
+        }
+    }
+    None
+}
+
+/// Split viewer arguments into paths and the optional initial scroll target.
+fn parse_open_args(args: &[String]) -> Result<(Vec<&str>, Option<ScrollTarget>), String> {
+    let mut paths = Vec::new();
+    let mut scroll_to = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--line" | "--heading" | "--find" => {
+                let value = iter.next().ok_or_else(|| format!("missing value for {}", arg))?;
+                scroll_to = Some(match arg.as_str() {
+                    "--line" => ScrollTarget::Line(value.parse().map_err(|_| format!("invalid line '{}'", value))?),
+                    "--heading" => ScrollTarget::Heading(value.clone()),
+                    _ => ScrollTarget::Find(value.clone()),
+                });
+            }
+            _ => paths.push(arg.as_str()),
+        }
+    }
+    Ok((paths, scroll_to))
+}
+
+/// Expand a command-line path. Shells normally expand globs themselves; this covers
+/// quoted patterns (and shells that don't), matching `*` and `?` in any component
+/// (`docs/*/notes.md`).
+fn expand_path_arg(arg: &str) -> Vec<PathBuf> {
+    let path = PathBuf::from(arg);
+    if path.exists() || !arg.contains(['*', '?']) {
+        return vec![path];
+    }
+
+    // One component at a time: patterns before the last match directories, the last files
+    let components: Vec<Component> = path.components().collect();
+    let mut matches = vec![PathBuf::new()];
+    for (i, component) in components.iter().enumerate() {
+        let last = i + 1 == components.len();
+        let Some(pattern) = component.as_os_str().to_str().filter(|c| c.contains(['*', '?'])) else {
+            matches = matches.into_iter().map(|m| m.join(component)).collect();
+            continue;
+        };
+        matches = matches.iter()
+            .flat_map(|dir| {
+                let read_from = if dir.as_os_str().is_empty() { Path::new(".") } else { dir.as_path() };
+                std::fs::read_dir(read_from)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter(|entry| entry.file_name().to_str().is_some_and(|name| glob_match(pattern, name)))
+                    .map(|entry| dir.join(entry.file_name()))
+                    .filter(|p| if last { p.is_file() } else { p.is_dir() })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    matches.retain(|p| p.is_file());
+    if matches.is_empty() {
+        eprintln!("marrow: no files match '{}'", arg);
+    }
+    matches.sort();
+    matches
+}
+
+/// Match `name` against a pattern where `*` is any run of characters and `?` is one character.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Focus the window already showing `path` (scrolling it to `scroll_to`), or open a new one.
+fn open_or_focus(
+    event_loop: &EventLoopWindowTarget<UserEvent>,
+    proxy: &EventLoopProxy<UserEvent>,
+    path: &PathBuf,
+    scroll_to: Option<&ScrollTarget>,
+    settings: &Arc<Mutex<AllSettings>>,
+    windows: &mut HashMap<WindowId, AppWindow>,
+) {
+    if let Some(existing_id) = find_window_for_path(windows, path) {
+        if let Some(app_window) = windows.get(&existing_id) {
+            app_window.window.set_focus();
+            if let Some(target) = scroll_to {
+                let _ = app_window.webview.evaluate_script(&format!("scrollToTarget({})", target.to_js()));
+            }
+        }
+    } else if let Ok((id, app_window)) = create_window(event_loop, proxy.clone(), Some(path), false, scroll_to, settings, windows) {
+        windows.insert(id, app_window);
+    }
+}
+
+fn create_window(
+    event_loop: &EventLoopWindowTarget<UserEvent>,
+    proxy: EventLoopProxy<UserEvent>,
+    path: Option<&PathBuf>,
+    from_stdin: bool,
+    scroll_to: Option<&ScrollTarget>,
+    settings: &Arc<Mutex<AllSettings>>,
+    existing_windows: &HashMap<WindowId, AppWindow>,
+) -> Result<(WindowId, AppWindow), Box<dyn std::error::Error>> {
+    // Extract file extension for per-extension settings
+    let extension = path
+        .and_then(|p| p.extension())
+        .and_then(|e| e.to_str())
+        .unwrap_or("md")
+        .to_string();
+
+    let all_settings = settings.lock().unwrap();
+    let current_settings = all_settings.get_for_extension(&extension).clone();
+    drop(all_settings);
+
+    // Relative links in piped markdown resolve against the working directory
+    let base_dir = if from_stdin {
+        std::env::current_dir().ok()
+    } else {
+        path.and_then(|p| p.parent()).map(Path::to_path_buf)
+    };
+    let is_notebook = extension == "ipynb";
+    let options = RenderOptions {
+        base_dir: base_dir.clone(),
+        file: if from_stdin { None } else { path.cloned() },
+        truncate_outputs: true,
+    };
+
+    // Load and render content based on file type
+    let (_content, filename, rendered, full_html) = if is_notebook {
+        let filename = path
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or("untitled")
+            .to_string();
+
+        match path.and_then(|p| std::fs::read_to_string(p).ok()) {
+            Some(json_content) => {
+                match serde_json::from_str::<Notebook>(&json_content) {
+                    Ok(notebook) => {
+                        let rendered = render_notebook(&notebook, &options);
+                        let html = build_full_html_notebook(&rendered.html, &rendered.toc, &current_settings, &extension);
+                        (json_content, filename, rendered, html)
+                    }
+                    Err(e) => {
+                        let error_md = format!("# Error\n\nCould not parse notebook: {}", e);
+                        let rendered = render_markdown(&error_md, &options);
+                        let html = build_full_html_markdown(&error_md, &rendered.html, &rendered.toc, &current_settings, &extension);
+                        (error_md, "Error".to_string(), rendered, html)
+                    }
+                }
+            }
+            None => {
+                let error_md = "# Error\n\nCould not load file".to_string();
+                let rendered = render_markdown(&error_md, &options);
+                let html = build_full_html_markdown(&error_md, &rendered.html, &rendered.toc, &current_settings, &extension);
+                (error_md, "Error".to_string(), rendered, html)
+            }
+        }
+    } else {
+        let (content, filename) = if from_stdin {
+            // Filled in by StdinChunk events once the page is ready
+            (String::new(), "stdin".to_string())
+        } else {
+            load_file(path)
+        };
+        let rendered = render_markdown(&content, &options);
+        let source = rendered.source_map.lines().join("\n");
+        let full_html = build_full_html_markdown(&source, &rendered.html, &rendered.toc, &current_settings, &extension);
+        (content, filename, rendered, full_html)
+    };
+
+    let title = window_title(&rendered, &filename);
+    let pending_blocks = rendered.pending_blocks;
+    let truncated_outputs = rendered.truncated_outputs;
+
+    // Calculate window size (use settings, add TOC width if visible)
+    let width = current_settings.window_width + if current_settings.toc_visible { 200.0 } else { 0.0 };
+    let height = current_settings.window_height;
+
+    let builder = WindowBuilder::new()
+        .with_title(title)
+        .with_inner_size(LogicalSize::new(width, height));
+
+    let window = builder.build(event_loop)?;
+
+    // Apply cascade position after window is created
+    if let Some(pos) = calculate_cascade_position(existing_windows) {
+        window.set_outer_position(pos);
+    }
+    let window = Arc::new(window);
+    let window_clone = Arc::clone(&window);
+    let window_id = window.id();
+    let proxy_clone = proxy.clone();
+    let settings_clone = Arc::clone(settings);
+
+    // Move base_dir into the navigation handler
+    let nav_base_dir = base_dir;
+    let nav_proxy = proxy.clone();
+
+    // Picked up by scrollToTarget() once the page has initialized
+    let init_script = match scroll_to {
+        Some(target) => format!("window.initialScrollTarget = {};", target.to_js()),
+        None => String::new(),
+    };
+
+    let webview = WebViewBuilder::new()
+        .with_html(&full_html)
+        .with_initialization_script(&init_script)
+        .with_ipc_handler(move |req| {
+            let msg = req.body();
+            if msg.starts_with("resize:") {
+                // Format: "resize:width:height"
+                let parts: Vec<&str> = msg.split(':').collect();
+                if parts.len() == 3 {
+                    if let (Ok(width), Ok(height)) = (parts[1].parse::<f64>(), parts[2].parse::<f64>()) {
+                        window_clone.set_inner_size(LogicalSize::new(width, height));
+                    }
+                }
+            } else if let Some(text) = msg.strip_prefix("clipboard:") {
+                // Format: "clipboard:text_to_copy"
+                if let Ok(mut clipboard) = arboard::Clipboard::new() {
+                    let _ = clipboard.set_text(text);
+                }
+            } else if let Some(rest) = msg.strip_prefix("save_settings:") {
+                // Format: "save_settings:ext:{json}" e.g. "save_settings:md:{...}"
+                if let Some(colon_pos) = rest.find(':') {
+                    let ext = &rest[..colon_pos];
+                    let json = &rest[colon_pos + 1..];
+                    if let Ok(new_settings) = serde_json::from_str::<Settings>(json) {
+                        let mut all_settings = settings_clone.lock().unwrap();
+                        all_settings.set_for_extension(ext, new_settings);
+                        save_settings(&all_settings);
+                    }
+                }
+            } else if let Some(rest) = msg.strip_prefix("get_output_lines:") {
+                // Format: "get_output_lines:cell_idx:output_idx:amount"
+                let parts: Vec<&str> = rest.split(':').collect();
+                if parts.len() == 3 {
+                    let cell_idx: usize = parts[0].parse().unwrap_or(0);
+                    let output_idx: usize = parts[1].parse().unwrap_or(0);
+                    let amount = parts[2].to_string();
+                    let _ = proxy_clone.send_event(UserEvent::RequestOutputLines {
+                        window_id,
+                        cell_idx,
+                        output_idx,
+                        amount,
+                    });
+                }
+            } else {
+                match msg.as_str() {
+                    "close_window" => {
+                        let _ = proxy_clone.send_event(UserEvent::CloseWindow(window_id));
+                    }
+                    "quit_app" => {
+                        let _ = proxy_clone.send_event(UserEvent::QuitApp);
+                    }
+                    "ready" => {
+                        let _ = proxy_clone.send_event(UserEvent::PageReady(window_id));
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .with_navigation_handler(move |url| {
+            // Allow internal navigation
+            if url.starts_with("about:") || url.starts_with("data:") {
+                return true;
+            }
+            // Open http/https links in default browser (and mailto: links in the mail app)
+            if url.starts_with("http://") || url.starts_with("https://") || url.starts_with("mailto:") {
+                let _ = std::process::Command::new("open").arg(&url).spawn();
+                return false;
+            }
+            // Handle file:// URLs; notes (e.g. wiki-links) open in Marrow, at the heading
+            if let Some(file_url) = url.strip_prefix("file://") {
+                let (file_path, fragment) = match file_url.split_once('#') {
+                    Some((file_path, fragment)) => (file_path, Some(fragment)),
+                    None => (file_url, None),
+                };
+                let decoded = urlencoding::decode(file_path).unwrap_or_else(|_| file_path.into());
+                let path = PathBuf::from(decoded.as_ref());
+                let is_note = path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| matches!(e.to_lowercase().as_str(), "md" | "markdown" | "ipynb"));
+                if is_note && path.is_file() {
+                    let scroll_to = fragment
+                        .map(|f| urlencoding::decode(f).map(|f| f.into_owned()).unwrap_or_else(|_| f.to_string()))
+                        .map(ScrollTarget::Heading);
+                    let _ = nav_proxy.send_event(UserEvent::OpenFiles(OpenRequest { paths: vec![path], scroll_to }));
+                } else if path.exists() {
+                    let _ = std::process::Command::new("open").arg(&path).spawn();
+                }
+                return false;
+            }
+            // Local file link - resolve relative to markdown file's directory
+            if let Some(ref base) = nav_base_dir {
+                let decoded = urlencoding::decode(&url).unwrap_or_else(|_| url.clone().into());
+                let path = base.join(decoded.as_ref());
+                if path.exists() {
+                    let _ = std::process::Command::new("open").arg(&path).spawn();
+                    return false;
+                }
+            }
+            // Block navigation to unknown URLs
+            false
+        })
+        .build(&window)?;
+
+    let file_path = path.cloned();
+
+    // Set up file watcher for live reload (including the files it includes)
+    let watched = file_path.as_deref().map(watched_files).unwrap_or_default();
+    let watcher = if watched.is_empty() {
+        None
+    } else {
+        setup_file_watcher(&watched, window_id, proxy)
+    };
+
+    Ok((window_id, AppWindow {
+        window,
+        webview,
+        file_path,
+        truncated_outputs,
+        watcher,
+        watched_files: watched,
+        last_reload: Instant::now(),
+        stream: from_stdin.then(String::new),
+        page_ready: false,
+        pending_blocks,
+        rerender_when_ready: false,
+    }))
+}
+
+/// Open the windows for `args` (or hand them to the running instance) and run the event loop.
+pub fn run(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let (path_args, scroll_to) = match parse_open_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("marrow: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Every path opens a window; `-` renders markdown piped on stdin as it streams in
+    let read_stdin = path_args.contains(&"-");
+    let request = OpenRequest {
+        paths: path_args.iter()
+            .filter(|arg| **arg != "-")
+            .flat_map(|arg| expand_path_arg(arg))
+            .map(|path| path.canonicalize().unwrap_or(path))
+            .collect(),
+        scroll_to,
+    };
+
+    // Hand the files to an already running Marrow instead of starting a second app.
+    // Stdin can't be handed over, so `marrow -` always gets its own process.
+    #[cfg(unix)]
+    let instance_running = match instance_socket_path().and_then(|path| UnixStream::connect(path).ok()) {
+        Some(stream) if !read_stdin && !request.paths.is_empty() => {
+            send_to_instance(stream, &InstanceMessage::Open(request))?;
+            return Ok(());
+        }
+        stream => stream.is_some(),
+    };
+
+    // Load persistent settings
+    let settings = Arc::new(Mutex::new(load_settings()));
+
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+    let proxy = event_loop.create_proxy();
+    let mut windows: HashMap<WindowId, AppWindow> = HashMap::new();
+
+    // Renderer commands run off the event loop; redraw the windows when one finishes
+    let render_proxy = Mutex::new(proxy.clone());
+    marrow::external::render_in_background(move || {
+        if let Ok(proxy) = render_proxy.lock() {
+            let _ = proxy.send_event(UserEvent::BlocksRendered);
+        }
+    });
+
+    // Become the instance that later invocations forward to (unless one is already running)
+    #[cfg(unix)]
+    let instance_socket = if instance_running { None } else { listen_for_instances(proxy.clone()) };
+
+    // Only create initial windows for files passed via command line (each cascades from the last)
+    for path in &request.paths {
+        if find_window_for_path(&windows, path).is_some() {
+            continue;
+        }
+        let (id, app_window) = create_window(&event_loop, proxy.clone(), Some(path), false, request.scroll_to.as_ref(), &settings, &windows)?;
+        windows.insert(id, app_window);
+    }
+    if read_stdin {
+        let (id, app_window) = create_window(&event_loop, proxy.clone(), None, true, None, &settings, &windows)?;
+        spawn_stdin_reader(id, proxy.clone());
+        windows.insert(id, app_window);
+    }
+
+    event_loop.run(move |event, event_loop, control_flow| {
+        *control_flow = ControlFlow::Wait;
+
+        match event {
+            TaoEvent::Opened { urls } => {
+                for url in urls {
+                    if let Ok(path) = url.to_file_path() {
+                        open_or_focus(event_loop, &proxy, &path, None, &settings, &mut windows);
+                    }
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::OpenFiles(request)) => {
+                for path in &request.paths {
+                    open_or_focus(event_loop, &proxy, path, request.scroll_to.as_ref(), &settings, &mut windows);
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::ReloadWindows(target)) => {
+                let ids = control_targets(&windows, &target);
+                marrow::external::forget_failures();
+                marrow::markdown::forget_vault_files();
+                for id in &ids {
+                    if let Some(app_window) = windows.get_mut(id) {
+                        if app_window.stream.is_some() {
+                            refresh_stream(app_window);
+                        } else if let Some(js) = reload_file_content(app_window) {
+                            let _ = app_window.webview.evaluate_script(&js);
+                        }
+                    }
+                }
+                let _ = target.reply.send(ids.len());
+            }
+            TaoEvent::UserEvent(UserEvent::BlocksRendered) => {
+                for app_window in windows.values_mut().filter(|app_window| app_window.pending_blocks) {
+                    if !app_window.page_ready {
+                        app_window.rerender_when_ready = true;
+                    } else if app_window.stream.is_some() {
+                        refresh_stream(app_window);
+                    } else if let Some(js) = reload_file_content(app_window) {
+                        let _ = app_window.webview.evaluate_script(&js);
+                    }
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::ScrollWindows(target, scroll_to)) => {
+                let ids = control_targets(&windows, &target);
+                let js = format!("scrollToTarget({})", scroll_to.to_js());
+                for id in &ids {
+                    if let Some(app_window) = windows.get(id) {
+                        let _ = app_window.webview.evaluate_script(&js);
+                    }
+                }
+                let _ = target.reply.send(ids.len());
+            }
+            TaoEvent::UserEvent(UserEvent::SetTheme(target, theme)) => {
+                let ids = control_targets(&windows, &target);
+                let js = format!("setTheme({})", serde_json::to_string(&theme).unwrap_or_default());
+                for id in &ids {
+                    if let Some(app_window) = windows.get(id) {
+                        let _ = app_window.webview.evaluate_script(&js);
+                    }
+                }
+                let _ = target.reply.send(ids.len());
+            }
+            TaoEvent::UserEvent(UserEvent::CloseWindows(target)) => {
+                let ids = control_targets(&windows, &target);
+                for id in &ids {
+                    windows.remove(id);
+                }
+                let _ = target.reply.send(ids.len());
+                if windows.is_empty() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            TaoEvent::LoopDestroyed => {
+                #[cfg(unix)]
+                if let Some(path) = &instance_socket {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::CloseWindow(window_id)) => {
+                windows.remove(&window_id);
+                if windows.is_empty() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::QuitApp) => {
+                *control_flow = ControlFlow::Exit;
+            }
+            TaoEvent::UserEvent(UserEvent::RequestOutputLines { window_id, cell_idx, output_idx, amount }) => {
+                if let Some(app_window) = windows.get_mut(&window_id) {
+                    if let Some(truncated) = app_window.truncated_outputs.get_mut(&(cell_idx, output_idx)) {
+                        let (lines_html, hidden_remaining, is_complete) = if amount == "all" {
+                            // Send all remaining lines (between shown and tail)
+                            let remaining: Vec<_> = truncated.full_lines[truncated.shown_lines..truncated.total_lines - 10].to_vec();
+                            let html = remaining.join("\n");
+                            (html, 0usize, true)
+                        } else {
+                            // Send next N lines
+                            let n: usize = amount.parse().unwrap_or(50);
+                            let end = (truncated.shown_lines + n).min(truncated.total_lines - 10);
+                            let lines: Vec<_> = truncated.full_lines[truncated.shown_lines..end].to_vec();
+                            let html = lines.join("\n");
+                            truncated.shown_lines = end;
+                            let hidden = truncated.total_lines - 10 - end;
+                            (html, hidden, hidden == 0)
+                        };
+
+                        // Call back to JS
+                        let js = format!(
+                            "receiveOutputLines({}, {}, {}, {}, {})",
+                            cell_idx,
+                            output_idx,
+                            serde_json::to_string(&lines_html).unwrap_or_else(|_| "\"\"".to_string()),
+                            hidden_remaining,
+                            is_complete
+                        );
+                        let _ = app_window.webview.evaluate_script(&js);
+                    }
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::FileChanged(window_id)) => {
+                if let Some(app_window) = windows.get_mut(&window_id) {
+                    // Debounce: ignore if last reload was <100ms ago
+                    if app_window.last_reload.elapsed() < Duration::from_millis(100) {
+                        return;
+                    }
+                    app_window.last_reload = Instant::now();
+
+                    // Small delay to let file writes complete (avoid reading mid-write)
+                    std::thread::sleep(Duration::from_millis(50));
+
+                    // Give blocks whose renderer failed another try
+                    marrow::external::forget_failures();
+
+                    if let Some(js) = reload_file_content(app_window) {
+                        let _ = app_window.webview.evaluate_script(&js);
+                    }
+
+                    // Follow includes that were added or removed
+                    if let Some(path) = &app_window.file_path {
+                        let watched = watched_files(path);
+                        if watched != app_window.watched_files {
+                            app_window.watcher = setup_file_watcher(&watched, window_id, proxy.clone());
+                            app_window.watched_files = watched;
+                        }
+                    }
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::StdinChunk(window_id, chunk)) => {
+                if let Some(app_window) = windows.get_mut(&window_id) {
+                    if let Some(stream) = app_window.stream.as_mut() {
+                        stream.push_str(&chunk);
+                    }
+                    refresh_stream(app_window);
+                }
+            }
+            TaoEvent::UserEvent(UserEvent::PageReady(window_id)) => {
+                if let Some(app_window) = windows.get_mut(&window_id) {
+                    app_window.page_ready = true;
+                    refresh_stream(app_window);
+                    if std::mem::take(&mut app_window.rerender_when_ready) && app_window.stream.is_none() {
+                        if let Some(js) = reload_file_content(app_window) {
+                            let _ = app_window.webview.evaluate_script(&js);
+                        }
+                    }
+                }
+            }
+            TaoEvent::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                window_id,
+                ..
+            } => {
+                windows.remove(&window_id);
+                if windows.is_empty() {
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    });
+}
+
+// ============================================================================
+// REMOTE CONTROL (marrow ctl)
+// ============================================================================
+
+const CTL_USAGE: &str = "usage: marrow ctl (--path <file> | --all) <command>
+
+commands:
+  reload                  re-render from disk
+  scroll-to <heading|n>   scroll to a heading (text or slug) or source line n
+  theme <dark|light>      switch the color theme
+  close                   close the window";
+
+/// Drive windows of the running instance. Fails if no open window matches.
+pub fn run_ctl(args: &[String]) -> Result<(), String> {
+    let mut path: Option<PathBuf> = None;
+    let mut all = false;
+    let mut words: Vec<&str> = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--path" => {
+                let value = iter.next().ok_or("missing value for --path")?;
+                let value = PathBuf::from(value);
+                path = Some(value.canonicalize().map_err(|e| format!("{}: {}", value.display(), e))?);
+            }
+            "--all" => all = true,
+            "-h" | "--help" => {
+                println!("{}", CTL_USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'\n{}", arg, CTL_USAGE)),
+            _ => words.push(arg),
+        }
+    }
+    if path.is_some() == all {
+        return Err(format!("pass exactly one of --path and --all\n{}", CTL_USAGE));
+    }
+
+    let command = match words.as_slice() {
+        ["reload"] => ControlCommand::Reload,
+        ["close"] => ControlCommand::Close,
+        ["scroll-to", target] => ControlCommand::ScrollTo(match target.parse() {
+            Ok(line) => ScrollTarget::Line(line),
+            Err(_) => ScrollTarget::Heading(target.to_string()),
+        }),
+        ["theme", theme @ ("dark" | "light")] => ControlCommand::Theme(theme.to_string()),
+        ["theme", theme] => return Err(format!("unknown theme '{}' (expected dark or light)", theme)),
+        _ => return Err(CTL_USAGE.to_string()),
+    };
+    send_control(path, command)
+}
+
+#[cfg(unix)]
+fn send_control(path: Option<PathBuf>, command: ControlCommand) -> Result<(), String> {
+    let stream = instance_socket_path()
+        .and_then(|socket| UnixStream::connect(socket).ok())
+        .ok_or("Marrow is not running")?;
+    let missing = match &path {
+        Some(path) => format!("no open window shows {}", path.display()),
+        None => "no windows are open".to_string(),
+    };
+    let reply = send_to_instance(stream, &InstanceMessage::Control { path, command })
+        .map_err(|e| e.to_string())?;
+    match reply.trim().parse::<usize>() {
+        Ok(count) if count > 0 => Ok(()),
+        _ => Err(missing),
+    }
+}
+
+#[cfg(not(unix))]
+fn send_control(_path: Option<PathBuf>, _command: ControlCommand) -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
+
+// ============================================================================
+// FILE LOADING
+// ============================================================================
+
+fn load_file(path: Option<&PathBuf>) -> (String, String) {
+    if let Some(path) = path {
+        let filename = path.file_name().and_then(|n| n.to_str()).unwrap_or("untitled").to_string();
+
+        // Check if it's a Jupyter notebook
+        if path.extension().map(|e| e == "ipynb").unwrap_or(false) {
+            match std::fs::read_to_string(path) {
+                Ok(contents) => {
+                    match serde_json::from_str::<Notebook>(&contents) {
+                        Ok(notebook) => (notebook_to_markdown(&notebook), filename),
+                        Err(e) => (format!("# Error\n\nCould not parse notebook: {}", e), "Error".to_string()),
+                    }
+                }
+                Err(e) => (format!("# Error\n\nCould not load file: {}", e), "Error".to_string()),
+            }
+        } else {
+            match std::fs::read_to_string(path) {
+                Ok(c) => (c, filename),
+                Err(e) => (format!("# Error\n\nCould not load file: {}", e), "Error".to_string()),
+            }
+        }
+    } else {
+        ("# Welcome to Marrow\n\nOpen a markdown file to get started.\n\nDrag and drop a `.md` or `.ipynb` file or open one with Marrow.".to_string(), "Marrow".to_string())
+    }
+}
+
@@ -0,0 +1,51 @@
+// ============================================================================
+// DIRECTORY CONFIG
+// ============================================================================
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Name of the per-directory config file.
+pub const CONFIG_FILE: &str = ".marrow.json";
+
+/// Rendering options for the documents in a directory tree, read from the nearest
+/// `.marrow.json` in the document's directory or one of its parents:
+///
+/// ```json
+/// {
+///   "repo_url": "https://github.com/owner/repo",
+///   "katex_macros": { "\\RR": "\\mathbb{R}" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct DirConfig {
+    /// Repository that `#123` and `owner/repo#123` references link to
+    #[serde(default)]
+    pub repo_url: Option<String>,
+    /// KaTeX macros for every document (front matter `katex_macros` add to and override these)
+    #[serde(default)]
+    pub katex_macros: BTreeMap<String, String>,
+}
+
+impl DirConfig {
+    /// The config that applies to documents in `dir` (the default if there is none, or it
+    /// can't be parsed).
+    pub fn load(dir: Option<&Path>) -> Self {
+        let Some(dir) = dir.and_then(|dir| dir.canonicalize().ok()) else { return Self::default() };
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    /// Host part of `repo_url` (`https://github.com`), for `owner/repo#123` references.
+    pub fn repo_host(&self) -> Option<&str> {
+        let url = self.repo_url.as_deref()?.trim_end_matches('/');
+        let scheme_end = url.find("://")? + 3;
+        let host_end = url[scheme_end..].find('/').map_or(url.len(), |i| scheme_end + i);
+        Some(&url[..host_end])
+    }
+}
@@ -0,0 +1,200 @@
+// ============================================================================
+// EXTERNAL RENDERERS
+// ============================================================================
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// How long a renderer may run before it is killed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Results kept before the cache is cleared (live reload re-renders every block).
+const CACHE_LIMIT: usize = 256;
+
+/// Where package managers put tools; apps started from Finder only get `/usr/bin:/bin`.
+const EXTRA_PATH: &str = "/opt/homebrew/bin:/usr/local/bin";
+
+/// Result of each command and source; `None` while it runs in the background. Failures
+/// stay until [`forget_failures`], so a broken renderer isn't re-run on every redraw.
+type Cache = HashMap<u64, Option<Result<String, String>>>;
+
+fn cache() -> &'static Mutex<Cache> {
+    static CACHE: OnceLock<Mutex<Cache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+fn renderers() -> &'static RwLock<BTreeMap<String, String>> {
+    static RENDERERS: OnceLock<RwLock<BTreeMap<String, String>>> = OnceLock::new();
+    RENDERERS.get_or_init(Default::default)
+}
+
+/// Called when a background render finishes (see [`render_in_background`]).
+static ON_RENDERED: OnceLock<Box<dyn Fn() + Send + Sync>> = OnceLock::new();
+
+/// Set the shell commands that render fenced blocks, by language (`"dot"` -> `dot -Tsvg`).
+/// These come from the user's own config; a document can't choose commands to run.
+pub fn set_renderers(commands: BTreeMap<String, String>) {
+    if let Ok(mut renderers) = renderers().write() {
+        *renderers = commands;
+    }
+}
+
+/// The command that renders fenced blocks of `lang`, if one is set.
+pub fn renderer_for(lang: &str) -> Option<String> {
+    renderers().read().ok()?.get(lang).cloned()
+}
+
+/// Run commands that aren't cached yet on their own threads instead of waiting for them,
+/// and call `on_rendered` when one finishes so the document can be rendered again. Used by
+/// the viewer, whose event loop must not wait for a slow command.
+pub fn render_in_background(on_rendered: impl Fn() + Send + Sync + 'static) {
+    let _ = ON_RENDERED.set(Box::new(on_rendered));
+}
+
+/// Render a code block by piping `source` into the shell `command` (e.g. `dot -Tsvg`),
+/// which must print an SVG, PNG or JPEG image. Returns the image as a data URI, or a
+/// message saying why it failed. Results are cached by command and source. With
+/// [`render_in_background`], returns `None` while the command is still running.
+pub fn render_with_command(command: &str, source: &str) -> Option<Result<String, String>> {
+    let mut hasher = DefaultHasher::new();
+    (command, source).hash(&mut hasher);
+    let key = hasher.finish();
+
+    if let Some(entry) = cache().lock().ok().and_then(|cache| cache.get(&key).cloned()) {
+        return entry;
+    }
+    let Some(on_rendered) = ON_RENDERED.get() else {
+        let result = render(command, source);
+        store(key, Some(result.clone()));
+        return Some(result);
+    };
+
+    store(key, None);
+    let (command, source) = (command.to_string(), source.to_string());
+    std::thread::spawn(move || {
+        store(key, Some(render(&command, &source)));
+        on_rendered();
+    });
+    None
+}
+
+/// Drop cached failures so those blocks are tried again (when their document reloads,
+/// the renderer may have been installed or the source fixed).
+pub fn forget_failures() {
+    if let Ok(mut cache) = cache().lock() {
+        cache.retain(|_, entry| !matches!(entry, Some(Err(_))));
+    }
+}
+
+fn render(command: &str, source: &str) -> Result<String, String> {
+    run(command, source).and_then(|output| image_data_uri(command, &output))
+}
+
+fn store(key: u64, entry: Option<Result<String, String>>) {
+    if let Ok(mut cache) = cache().lock() {
+        if cache.len() >= CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(key, entry);
+    }
+}
+
+/// Run `command` with `source` on stdin and return its stdout.
+fn run(command: &str, source: &str) -> Result<Vec<u8>, String> {
+    let path = match std::env::var("PATH") {
+        Ok(path) if !path.is_empty() => format!("{}:{}", path, EXTRA_PATH),
+        _ => format!("/usr/bin:/bin:{}", EXTRA_PATH),
+    };
+    let mut shell = Command::new("sh");
+    shell.arg("-c")
+        .arg(command)
+        .env("PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // In a process group of its own, so a timeout also stops what `sh` started
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let mut child = shell.spawn().map_err(|e| format!("cannot run `{}`: {}", command, e))?;
+
+    // Feed stdin and drain the pipes on their own threads so a chatty command can't block
+    let mut stdin = child.stdin.take();
+    let input = source.to_string();
+    let writer = std::thread::spawn(move || {
+        if let Some(stdin) = stdin.as_mut() {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+    });
+    let stdout = child.stdout.take().map(read_to_end);
+    let stderr = child.stderr.take().map(read_to_end);
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() < TIMEOUT => std::thread::sleep(Duration::from_millis(10)),
+            Ok(None) => {
+                kill_group(&mut child);
+                let _ = child.wait();
+                return Err(format!("`{}` timed out after {} seconds", command, TIMEOUT.as_secs()));
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    };
+    let _ = writer.join();
+    let stdout = stdout.and_then(|reader| reader.join().ok()).unwrap_or_default();
+    let stderr = stderr.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+    if !status.success() {
+        let message = String::from_utf8_lossy(&stderr).trim().to_string();
+        return Err(if message.is_empty() {
+            format!("`{}` failed ({})", command, status)
+        } else {
+            format!("`{}` failed: {}", command, message)
+        });
+    }
+    Ok(stdout)
+}
+
+/// Kill the command and everything it started; their pipes then close, ending the
+/// reader threads.
+fn kill_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: signals the process group created for this child only
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+        return;
+    }
+    let _ = child.kill();
+}
+
+fn read_to_end(mut pipe: impl Read + Send + 'static) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        let _ = pipe.read_to_end(&mut buffer);
+        buffer
+    })
+}
+
+/// A data URI for the image a renderer printed.
+fn image_data_uri(command: &str, output: &[u8]) -> Result<String, String> {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    let mime = if output.starts_with(b"\x89PNG") {
+        "image/png"
+    } else if output.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if String::from_utf8_lossy(&output[..output.len().min(4096)]).contains("<svg") {
+        "image/svg+xml"
+    } else if output.is_empty() {
+        return Err(format!("`{}` printed nothing", command));
+    } else {
+        return Err(format!("`{}` didn't print an SVG, PNG or JPEG image", command));
+    };
+    Ok(format!("data:{};base64,{}", mime, STANDARD.encode(output)))
+}
@@ -0,0 +1,161 @@
+// ============================================================================
+// SYNTAX HIGHLIGHTING
+// ============================================================================
+
+use std::sync::OnceLock;
+use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::html_escape;
+
+/// Grammars compiled into the binary (Sublime Text's default packages).
+pub(crate) fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Fence languages and kernel names that aren't a grammar's name or file extension.
+const ALIASES: &[(&str, &str)] = &[
+    ("shell", "sh"),
+    ("console", "sh"),
+    ("zsh", "sh"),
+    ("python3", "py"),
+    ("ipython", "py"),
+    ("ipython3", "py"),
+    ("c++", "cpp"),
+    ("jsx", "js"),
+    ("golang", "go"),
+];
+
+/// The grammar for a fence language or notebook kernel (`rust`, `py`, `JavaScript`).
+pub(crate) fn find_syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    let syntaxes = syntax_set();
+    let lang = lang.to_lowercase();
+    let lang = ALIASES.iter().find(|(alias, _)| *alias == lang).map_or(lang.as_str(), |(_, token)| token);
+    syntaxes.find_syntax_by_token(lang)
+}
+
+/// Scopes mapped to the highlight.js classes that the github-dark theme and its light
+/// overrides already style. The innermost scope with a match wins; within one scope,
+/// the first matching prefix.
+const SCOPE_CLASSES: &[(&str, &str)] = &[
+    ("comment", "hljs-comment"),
+    ("string.regexp", "hljs-regexp"),
+    ("string", "hljs-string"),
+    ("constant.numeric", "hljs-number"),
+    ("constant.character.escape", "hljs-string"),
+    ("constant.language", "hljs-literal"),
+    ("constant.other.placeholder", "hljs-subst"),
+    ("constant", "hljs-variable"),
+    ("keyword.operator", "hljs-operator"),
+    ("keyword", "hljs-keyword"),
+    ("storage", "hljs-keyword"),
+    ("support.type", "hljs-type"),
+    ("support.class", "hljs-title class_"),
+    ("support.function", "hljs-built_in"),
+    ("support.constant", "hljs-literal"),
+    ("entity.name.function", "hljs-title function_"),
+    ("variable.function", "hljs-title function_"),
+    ("entity.name.tag", "hljs-name"),
+    ("entity.name.section", "hljs-section"),
+    ("entity.name", "hljs-title class_"),
+    ("entity.other.attribute-name", "hljs-attr"),
+    ("entity.other.inherited-class", "hljs-title class_ inherited__"),
+    ("variable.parameter", "hljs-params"),
+    ("variable.language", "hljs-variable language_"),
+    ("variable.other.constant", "hljs-variable"),
+    ("meta.preprocessor", "hljs-meta"),
+    ("meta.annotation", "hljs-meta"),
+    ("meta.attribute", "hljs-meta"),
+    ("markup.heading", "hljs-section"),
+    ("markup.bold", "hljs-strong"),
+    ("markup.italic", "hljs-emphasis"),
+    ("markup.inserted", "hljs-addition"),
+    ("markup.deleted", "hljs-deletion"),
+    ("markup.underline.link", "hljs-link"),
+    ("markup.quote", "hljs-quote"),
+    ("markup.list", "hljs-bullet"),
+    ("markup.raw", "hljs-code"),
+];
+
+fn scope_classes() -> &'static [(Scope, &'static str)] {
+    static CLASSES: OnceLock<Vec<(Scope, &'static str)>> = OnceLock::new();
+    CLASSES.get_or_init(|| {
+        SCOPE_CLASSES.iter()
+            .filter_map(|(scope, class)| Some((Scope::new(scope).ok()?, *class)))
+            .collect()
+    })
+}
+
+fn token_class(stack: &ScopeStack) -> Option<&'static str> {
+    stack.as_slice().iter().rev().find_map(|scope| {
+        scope_classes().iter().find(|(prefix, _)| prefix.is_prefix_of(*scope)).map(|(_, class)| *class)
+    })
+}
+
+/// Highlight `code` as HTML token spans (`<span class="hljs-keyword">`), or `None` if
+/// there is no grammar for `lang`. Spans never cross a line break.
+pub fn highlight_code_html(code: &str, lang: &str) -> Option<String> {
+    let syntaxes = syntax_set();
+    let mut state = ParseState::new(find_syntax(lang)?);
+    let mut stack = ScopeStack::new();
+    let mut html = Tokens::default();
+
+    for line in LinesWithEndings::from(code) {
+        let ops = state.parse_line(line, syntaxes).ok()?;
+        let mut pos = 0;
+        for (index, op) in ops {
+            html.push(&line[pos..index], token_class(&stack));
+            pos = index;
+            stack.apply(&op).ok()?;
+        }
+        html.push(&line[pos..], token_class(&stack));
+    }
+    Some(html.finish())
+}
+
+/// Highlighted HTML, merging neighbouring text of the same class into one span.
+#[derive(Default)]
+struct Tokens {
+    html: String,
+    class: Option<&'static str>,
+    text: String,
+}
+
+impl Tokens {
+    fn push(&mut self, text: &str, class: Option<&'static str>) {
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.add("\n", None);
+            }
+            self.add(part, class);
+        }
+    }
+
+    fn add(&mut self, text: &str, class: Option<&'static str>) {
+        if text.is_empty() {
+            return;
+        }
+        if class != self.class {
+            self.flush();
+            self.class = class;
+        }
+        self.text.push_str(text);
+    }
+
+    fn flush(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+        match self.class {
+            Some(class) => self.html.push_str(&format!(r#"<span class="{}">{}</span>"#, class, html_escape(&self.text))),
+            None => self.html.push_str(&html_escape(&self.text)),
+        }
+        self.text.clear();
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        self.html
+    }
+}
@@ -0,0 +1,220 @@
+// ============================================================================
+// INCLUDE DIRECTIVES
+// ============================================================================
+
+use std::path::{Path, PathBuf};
+
+/// How deeply included files may include further files.
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Inclusive, 1-based line range; `None` means from the start / to the end.
+type LineRange = (Option<usize>, Option<usize>);
+
+/// Markdown with its include directives replaced by the files they name.
+///
+/// A directive is a line of its own: `<!-- include: part2.md -->`, or mdBook's
+/// `{{#include part2.md}}` (optionally `{{#include code.rs:10:20}}` for a line range).
+/// Paths are relative to the including file and must stay inside the main document's
+/// directory. Files that are missing, outside it, or would include themselves, are
+/// replaced by a warning callout.
+#[derive(Debug, Clone, Default)]
+pub struct Expanded {
+    /// The combined document
+    pub text: String,
+    /// Every included file (canonical paths), in order of first inclusion
+    pub files: Vec<PathBuf>,
+    /// Source of each line of `text`: (0 for the main document or index into `files` + 1,
+    /// 1-based line in that file). Empty when there were no include directives.
+    origins: Vec<(usize, usize)>,
+    /// `files` as shown in `data-source` (relative to the main document's directory)
+    names: Vec<String>,
+    /// Whether a directive was replaced by a warning
+    warned: bool,
+}
+
+/// Expand the include directives in `markdown`, resolving paths against `base_dir`.
+/// `file` is the document's own path (`None` for stdin), so that an include leading back
+/// to it is reported as a cycle rather than expanded again.
+pub fn expand_includes(markdown: &str, base_dir: Option<&Path>, file: Option<&Path>) -> Expanded {
+    let mut expanded = Expanded::default();
+    if !markdown.contains("include") {
+        expanded.text = markdown.to_string();
+        return expanded;
+    }
+
+    let root = base_dir.and_then(|dir| dir.canonicalize().ok());
+    let mut stack: Vec<PathBuf> = file.and_then(|file| file.canonicalize().ok()).into_iter().collect();
+    expanded.expand(markdown, 0, 1, base_dir, root.as_deref(), &mut stack);
+    if expanded.files.is_empty() && !expanded.warned {
+        // Nothing was included: keep the text exactly as it was
+        expanded.text = markdown.to_string();
+        expanded.origins.clear();
+    }
+    expanded
+}
+
+impl Expanded {
+    /// Append `markdown` (from `file`, starting at its line `first_line`) to the text.
+    fn expand(&mut self, markdown: &str, file: usize, first_line: usize, dir: Option<&Path>, root: Option<&Path>, stack: &mut Vec<PathBuf>) {
+        let mut fence: Option<&str> = None;
+        for (index, line) in markdown.split_inclusive('\n').enumerate() {
+            let line_number = first_line + index;
+            let trimmed = line.trim();
+
+            if let Some(marker) = fence {
+                if trimmed.starts_with(marker) {
+                    fence = None;
+                }
+            } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                let first = trimmed.as_bytes()[0];
+                let len = trimmed.bytes().take_while(|&b| b == first).count();
+                fence = Some(&trimmed[..len]);
+                self.push_line(line, file, line_number);
+                continue;
+            }
+
+            // mdBook also expands includes inside code fences (to pull in source files)
+            let Some(spec) = include_directive(trimmed, fence.is_some()) else {
+                self.push_line(line, file, line_number);
+                continue;
+            };
+
+            match self.read_include(spec, dir, root, stack) {
+                Ok((path, content, include_start)) => {
+                    let index = match self.files.iter().position(|f| *f == path) {
+                        Some(index) => index,
+                        None => {
+                            let name = root
+                                .and_then(|root| path.strip_prefix(root).ok())
+                                .unwrap_or(&path)
+                                .to_string_lossy()
+                                .into_owned();
+                            self.files.push(path.clone());
+                            self.names.push(name);
+                            self.files.len() - 1
+                        }
+                    };
+                    stack.push(path.clone());
+                    self.expand(&content, index + 1, include_start, path.parent(), root, stack);
+                    stack.pop();
+                }
+                Err(message) => {
+                    let warning = format!("> [!caution] Cannot include {}: {}\n", spec, message);
+                    self.push_line(&warning, file, line_number);
+                    self.warned = true;
+                }
+            }
+        }
+    }
+
+    fn push_line(&mut self, line: &str, file: usize, line_number: usize) {
+        self.text.push_str(line);
+        if !line.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.origins.push((file, line_number));
+    }
+
+    /// Read the file an include spec names: (canonical path, content, first line number).
+    fn read_include(&self, spec: &str, dir: Option<&Path>, root: Option<&Path>, stack: &[PathBuf]) -> Result<(PathBuf, String, usize), String> {
+        let (Some(dir), Some(root)) = (dir, root) else { return Err("no base directory".to_string()) };
+        let (file, range) = split_line_range(spec);
+        let path = dir.join(file).canonicalize().map_err(|_| "file not found".to_string())?;
+        // Canonical, so `..` and symlinks can't reach outside either
+        if !path.starts_with(root) {
+            return Err("it is outside the document's folder".to_string());
+        }
+        if stack.contains(&path) {
+            return Err("it would include itself".to_string());
+        }
+        // The stack may start with the document itself, which isn't an include
+        if stack.iter().filter(|file| self.files.contains(file)).count() >= MAX_INCLUDE_DEPTH {
+            return Err(format!("includes are nested more than {} deep", MAX_INCLUDE_DEPTH));
+        }
+        let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+
+        match range {
+            Some((start, end)) => {
+                let start = start.unwrap_or(1).max(1);
+                let lines: Vec<&str> = content.split_inclusive('\n').collect();
+                let end = end.unwrap_or(lines.len()).min(lines.len());
+                let selected = if start <= end { lines[start - 1..end].concat() } else { String::new() };
+                Ok((path, selected, start))
+            }
+            None => Ok((path, content, 1)),
+        }
+    }
+
+    /// Name of the included file and line within it for 1-based `line` of `text`,
+    /// or `None` if the line belongs to the main document.
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let &(file, source_line) = self.origins.get(line.checked_sub(1)?)?;
+        let name = self.names.get(file.checked_sub(1)?)?;
+        Some((name, source_line))
+    }
+
+    /// Add `data-source="part2.md" data-source-lines="3-7"` to every element whose
+    /// `data-lines` start in an included file. `data-lines` themselves keep pointing
+    /// into the combined [`text`](Self::text).
+    pub fn annotate(&self, html: &str) -> String {
+        if self.files.is_empty() {
+            return html.to_string();
+        }
+        const ATTR: &str = "data-lines=\"";
+        let mut out = String::with_capacity(html.len());
+        let mut rest = html;
+        while let Some(pos) = rest.find(ATTR) {
+            let after = &rest[pos + ATTR.len()..];
+            let Some(end) = after.find('"') else { break };
+            out.push_str(&rest[..pos + ATTR.len() + end + 1]);
+
+            let range = after[..end].split_once('-')
+                .and_then(|(start, end)| Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?)));
+            if let Some((start, end)) = range {
+                if let Some((name, source_start)) = self.origin(start) {
+                    // Clamp the end to the last line that came from the same file
+                    let source_end = (start..=end).rev()
+                        .filter_map(|line| self.origin(line))
+                        .find(|(end_name, _)| *end_name == name)
+                        .map_or(source_start, |(_, line)| line.max(source_start));
+                    out.push_str(&format!(
+                        r#" data-source="{}" data-source-lines="{}-{}""#,
+                        crate::html_escape(name), source_start, source_end
+                    ));
+                }
+            }
+            rest = &after[end + 1..];
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// The path in an include directive line, if it is one.
+fn include_directive(line: &str, in_code_block: bool) -> Option<&str> {
+    if let Some(inner) = line.strip_prefix("{{#include").and_then(|rest| rest.strip_suffix("}}")) {
+        let spec = inner.trim();
+        return (inner.starts_with(char::is_whitespace) && !spec.is_empty()).then_some(spec);
+    }
+    if in_code_block {
+        return None;
+    }
+    let inner = line.strip_prefix("<!--")?.strip_suffix("-->")?.trim();
+    let spec = inner.strip_prefix("include:")?.trim();
+    (!spec.is_empty()).then_some(spec)
+}
+
+/// Split mdBook's line ranges off a path: `file.rs:10` (one line), `file.rs:10:20`,
+/// `file.rs:10:` (to the end) and `file.rs::20` (from the start).
+fn split_line_range(spec: &str) -> (&str, Option<LineRange>) {
+    let mut parts = spec.splitn(3, ':');
+    let file = parts.next().unwrap_or(spec);
+    let parse = |part: &str| part.trim().parse::<usize>().ok();
+    match (parts.next(), parts.next()) {
+        (None, _) => (file, None),
+        (Some(start), None) if parse(start).is_some() => (file, Some((parse(start), parse(start)))),
+        (Some(start), Some(end)) if start.is_empty() || parse(start).is_some() => (file, Some((parse(start), parse(end)))),
+        // Not a line range (e.g. an mdBook anchor name): include the whole file
+        _ => (file, None),
+    }
+}
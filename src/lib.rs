@@ -0,0 +1,164 @@
+//! Marrow's rendering pipeline as a library: markdown and Jupyter notebooks to HTML,
+//! without a window or webview.
+//!
+//! The HTML is exactly what the Marrow viewer displays. Block elements carry
+//! `data-lines="start-end"` attributes (1-based, inclusive) pointing back into the
+//! source, which is how smart copy recovers markdown from a selection.
+//!
+//! ```
+//! use marrow::{render_markdown, RenderOptions};
+//!
+//! let rendered = render_markdown("# Title\n\nSome *text*.\n", &RenderOptions::default());
+//! assert!(rendered.html.starts_with(r#"<h1 id="title" data-lines="1-"#));
+//! assert_eq!(rendered.toc[0].text, "Title");
+//! assert_eq!(rendered.toc[0].slug, "title");
+//! assert_eq!(rendered.source_map.source_for("3-3").as_deref(), Some("Some *text*."));
+//! ```
+//!
+//! Use the library with `default-features = false` to leave out the desktop app
+//! (tao/wry and friends).
+
+pub mod ansi;
+pub mod config;
+pub mod external;
+pub mod highlight;
+pub mod include;
+pub mod markdown;
+pub mod notebook;
+pub mod page;
+pub mod source_view;
+pub mod terminal;
+
+use serde::Serialize;
+use std::path::PathBuf;
+
+pub use ansi::{ansi_to_html, strip_ansi_codes};
+pub use config::DirConfig;
+pub use include::{expand_includes, Expanded};
+pub use markdown::{extract_toc, markdown_to_html, parse_front_matter, slugify, FrontMatter, Slugger};
+pub use notebook::{export_notebook_markdown, extract_notebook_toc, notebook_to_html, notebook_to_markdown, CellOutput, ExportedImage, Notebook, NotebookCell, StringOrArray, TruncatedOutput, TruncatedOutputs};
+pub use page::Settings;
+pub use source_view::source_view_html;
+pub use terminal::{markdown_to_ansi, notebook_to_ansi};
+
+// ============================================================================
+// PUBLIC API
+// ============================================================================
+
+/// One heading in the document outline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TocEntry {
+    /// Heading level, 1 (`#`) through 6 (`######`)
+    pub level: usize,
+    /// Plain heading text (inline markup stripped)
+    pub text: String,
+    /// Anchor id of the rendered heading (GitHub-compatible, unique within the document)
+    pub slug: String,
+    /// Classes given in the heading's `{.class}` attributes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub classes: Vec<String>,
+    /// 1-based source line of the heading (within its cell for notebooks)
+    pub line: usize,
+    /// Index of the notebook cell containing the heading (`None` for markdown files)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cell: Option<usize>,
+}
+
+/// Options shared by [`render_markdown`] and [`render_notebook`].
+#[derive(Debug, Clone, Default)]
+pub struct RenderOptions {
+    /// Directory that relative image paths are resolved against (and inlined as data URIs)
+    pub base_dir: Option<PathBuf>,
+    /// The document's own file (`None` for text from elsewhere), so an include that leads
+    /// back to it is reported as a cycle
+    pub file: Option<PathBuf>,
+    /// Show only the head and tail of long notebook outputs, keeping the full lines in
+    /// [`Rendered::truncated_outputs`] so a viewer can load the rest on demand
+    pub truncate_outputs: bool,
+}
+
+/// Result of rendering a document.
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    /// HTML fragment (no page shell, styles or scripts)
+    pub html: String,
+    /// Document outline in order of appearance
+    pub toc: Vec<TocEntry>,
+    /// Source lines that the `data-lines` attributes refer to
+    pub source_map: SourceMap,
+    /// Full text of truncated notebook outputs (empty unless `truncate_outputs` is set)
+    pub truncated_outputs: TruncatedOutputs,
+    /// YAML/TOML metadata at the top of a markdown file
+    pub front_matter: Option<FrontMatter>,
+    /// Some fenced blocks are still being drawn by their renderer; render again when
+    /// [`external::render_in_background`]'s callback runs
+    pub pending_blocks: bool,
+}
+
+/// Maps `data-lines` ranges back to markdown source.
+///
+/// For notebooks the map is empty: `data-lines` inside a markdown cell are relative
+/// to that cell's own source (see the cell's `data-cell-idx`).
+#[derive(Debug, Clone, Default)]
+pub struct SourceMap {
+    lines: Vec<String>,
+}
+
+impl SourceMap {
+    pub fn new(source: &str) -> Self {
+        Self { lines: source.lines().map(str::to_string).collect() }
+    }
+
+    /// All source lines; line `n` of a `data-lines` range is `lines()[n - 1]`.
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    /// Source text for a `data-lines` value such as `"3-7"`, or `None` if it is malformed
+    /// or out of range.
+    pub fn source_for(&self, data_lines: &str) -> Option<String> {
+        let (start, end) = data_lines.split_once('-')?;
+        let start: usize = start.trim().parse().ok()?;
+        let end: usize = end.trim().parse().ok()?;
+        if start == 0 || start > end || start > self.lines.len() {
+            return None;
+        }
+        Some(self.lines[start - 1..end.min(self.lines.len())].join("\n"))
+    }
+}
+
+/// Render a markdown document. Include directives are expanded first (see [`Expanded`]), so
+/// `data-lines`, the outline and the source map refer to the combined document.
+pub fn render_markdown(src: &str, options: &RenderOptions) -> Rendered {
+    let expanded = expand_includes(src, options.base_dir.as_deref(), options.file.as_deref());
+    let html = expanded.annotate(&markdown_to_html(&expanded.text, options.base_dir.as_deref()));
+    Rendered {
+        pending_blocks: markdown::has_pending_blocks(&html),
+        html,
+        toc: extract_toc(&expanded.text),
+        source_map: SourceMap::new(&expanded.text),
+        truncated_outputs: TruncatedOutputs::new(),
+        front_matter: parse_front_matter(src),
+    }
+}
+
+/// Render a parsed notebook (`serde_json::from_str::<Notebook>(json)`).
+pub fn render_notebook(notebook: &Notebook, options: &RenderOptions) -> Rendered {
+    let (html, toc, truncated_outputs) = notebook_to_html(notebook, options.base_dir.as_deref(), options.truncate_outputs);
+    Rendered {
+        pending_blocks: markdown::has_pending_blocks(&html),
+        html,
+        toc,
+        source_map: SourceMap::default(),
+        truncated_outputs,
+        front_matter: None,
+    }
+}
+
+/// Escape text for use in HTML content and double-quoted attributes.
+pub fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
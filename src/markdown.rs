@@ -0,0 +1,2144 @@
+// ============================================================================
+// MARKDOWN RENDERING
+// ============================================================================
+
+use pulldown_cmark::{Alignment, BlockQuoteKind, CowStr, LinkType, Options, Parser, Event, Tag, TagEnd, CodeBlockKind};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::config::DirConfig;
+use crate::external::{render_with_command, renderer_for};
+use crate::highlight::{find_syntax, highlight_code_html};
+use crate::html_escape;
+use crate::TocEntry;
+
+/// The pulldown-cmark extensions every markdown view (HTML, outline, terminal) parses with.
+pub(crate) fn parser_options() -> Options {
+    Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_GFM
+        | Options::ENABLE_HEADING_ATTRIBUTES
+        | Options::ENABLE_MATH
+}
+
+/// Append the headings of `markdown` to `toc` (used per notebook markdown cell; share
+/// `slugger` across cells so anchors are unique in the whole notebook).
+pub fn extract_headings_from_markdown(markdown: &str, toc: &mut Vec<TocEntry>, slugger: &mut Slugger) {
+    let source = prepare_source(markdown);
+    let markdown: &str = &source;
+    let parser = wikilink_events(markdown, Parser::new_ext(markdown, parser_options()).into_offset_iter());
+    let mut in_heading = false;
+    let mut current_level = 0;
+    let mut current_line = 0;
+    let mut current_text = String::new();
+    let mut current_id: Option<String> = None;
+    let mut current_classes: Vec<String> = Vec::new();
+
+    for (event, range) in parser {
+        match event {
+            Event::Start(Tag::Heading { level, id, classes, .. }) => {
+                in_heading = true;
+                current_id = id.map(|id| id.to_string());
+                current_classes = classes.iter().map(|class| class.to_string()).collect();
+                current_line = byte_offset_to_line(markdown, range.start);
+                current_level = level as usize;
+                current_text.clear();
+            }
+            Event::End(TagEnd::Heading(_)) if in_heading => {
+                in_heading = false;
+                // Slug every heading, even empty ones, so suffixes match the rendered ids
+                let slug = slugger.heading_id(&current_text, current_id.take().as_deref());
+                if !current_text.is_empty() {
+                    toc.push(TocEntry {
+                        level: current_level,
+                        text: current_text.clone(),
+                        slug,
+                        classes: std::mem::take(&mut current_classes),
+                        line: current_line,
+                        cell: None,
+                    });
+                }
+            }
+            Event::Text(text) if in_heading => {
+                current_text.push_str(&text);
+            }
+            Event::Code(code) | Event::InlineMath(code) if in_heading => {
+                current_text.push_str(&code);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Collect outline entries for every non-empty heading, in document order.
+pub fn extract_toc(markdown: &str) -> Vec<TocEntry> {
+    let mut toc = Vec::new();
+    extract_headings_from_markdown(markdown, &mut toc, &mut Slugger::default());
+    toc
+}
+
+/// Convert a byte offset in the source to a 1-based line number
+pub fn byte_offset_to_line(markdown: &str, byte_offset: usize) -> usize {
+    markdown[..byte_offset.min(markdown.len())]
+        .chars()
+        .filter(|&c| c == '\n')
+        .count() + 1
+}
+
+/// Resolve a relative image path against `base_dir` and inline it as a data URI.
+/// Absolute URLs, data URIs and unresolvable paths are returned unchanged.
+pub fn resolve_image_url(url: &str, base_dir: Option<&Path>) -> String {
+    use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+    // Already absolute URL or data URI
+    if url.starts_with("http://") || url.starts_with("https://")
+        || url.starts_with("file://") || url.starts_with("data:") {
+        return url.to_string();
+    }
+
+    // Try to resolve relative path and embed as data URI
+    if let Some(base) = base_dir {
+        let path = base.join(url);
+        if path.exists() {
+            if let Ok(data) = std::fs::read(&path) {
+                let mime = get_mime_type(&path);
+                let b64 = STANDARD.encode(&data);
+                return format!("data:{};base64,{}", mime, b64);
+            }
+        }
+    }
+
+    // Return as-is if we can't resolve
+    url.to_string()
+}
+
+fn get_mime_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("bmp") => "image/bmp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Link destination marking a wiki-link target produced by [`wikilink_events`].
+const WIKILINK_PREFIX: &str = "wikilink:";
+
+/// How deeply notes may embed other notes.
+const MAX_EMBED_DEPTH: usize = 3;
+
+thread_local! {
+    // Notes being transcluded right now, outermost first, to stop embed cycles
+    static EMBED_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Turn Obsidian `[[Target#Heading|alias]]` and `![[Target]]` text into link and image
+/// events with a `wikilink:` destination, resolved when rendering. Code, and text that
+/// involves escapes or entities, is left alone.
+fn wikilink_events<'a>(
+    markdown: &'a str,
+    events: impl Iterator<Item = (Event<'a>, Range<usize>)>,
+) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut out = Vec::new();
+    // pulldown splits `[[` into several text events; collect adjacent ones
+    let mut run: Vec<(Event<'a>, Range<usize>)> = Vec::new();
+    let mut in_code_block = false;
+
+    for (event, range) in events {
+        if matches!(event, Event::Text(_)) && !in_code_block {
+            run.push((event, range));
+            continue;
+        }
+        split_wikilinks(markdown, std::mem::take(&mut run), &mut out);
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            _ => {}
+        }
+        out.push((event, range));
+    }
+    split_wikilinks(markdown, run, &mut out);
+    out
+}
+
+fn split_wikilinks<'a>(markdown: &str, run: Vec<(Event<'a>, Range<usize>)>, out: &mut Vec<(Event<'a>, Range<usize>)>) {
+    let (Some(first), Some(last)) = (run.first(), run.last()) else { return };
+    let range = first.1.start..last.1.end;
+    let text: String = run.iter()
+        .filter_map(|(event, _)| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect();
+    if !text.contains("[[") || markdown.get(range.clone()) != Some(text.as_str()) {
+        out.extend(run);
+        return;
+    }
+
+    let text_event = |text: &str| (Event::Text(CowStr::from(text.to_string())), range.clone());
+    let mut rest = text.as_str();
+    while let Some(open) = rest.find("[[") {
+        let Some(len) = rest[open + 2..].find("]]") else { break };
+        let inner = &rest[open + 2..open + 2 + len];
+        if inner.trim().is_empty() || inner.contains('[') {
+            out.push(text_event(&rest[..open + 2]));
+            rest = &rest[open + 2..];
+            continue;
+        }
+        let embed = rest[..open].ends_with('!');
+        let before = &rest[..if embed { open - 1 } else { open }];
+        if !before.is_empty() {
+            out.push(text_event(before));
+        }
+
+        let (target, alias) = match inner.split_once('|') {
+            Some((target, alias)) => (target.trim(), Some(alias.trim())),
+            None => (inner.trim(), None),
+        };
+        let dest_url = CowStr::from(format!("{}{}", WIKILINK_PREFIX, target));
+        if embed {
+            // The alias (alt text or `300`/`300x200` size) travels in the title
+            let title = CowStr::from(alias.unwrap_or("").to_string());
+            out.push((Event::Start(Tag::Image { link_type: LinkType::Inline, dest_url, title, id: "".into() }), range.clone()));
+            out.push((Event::End(TagEnd::Image), range.clone()));
+        } else {
+            let label = match alias {
+                Some(alias) => alias.to_string(),
+                None => wikilink_label(target),
+            };
+            out.push((Event::Start(Tag::Link { link_type: LinkType::Inline, dest_url, title: "".into(), id: "".into() }), range.clone()));
+            out.push(text_event(&label));
+            out.push((Event::End(TagEnd::Link), range.clone()));
+        }
+        rest = &rest[open + 2 + len + 2..];
+    }
+    if !rest.is_empty() {
+        out.push(text_event(rest));
+    }
+}
+
+/// Split `Note#Heading` into the note name and heading (block references `#^id` are
+/// treated as links to the whole note).
+fn split_wikilink_target(target: &str) -> (&str, Option<&str>) {
+    match target.split_once('#') {
+        Some((name, heading)) if !heading.starts_with('^') && !heading.is_empty() => (name.trim(), Some(heading.trim())),
+        Some((name, _)) => (name.trim(), None),
+        None => (target, None),
+    }
+}
+
+/// Text shown for a wiki-link without an alias, as in Obsidian: `Note > Heading`.
+fn wikilink_label(target: &str) -> String {
+    match split_wikilink_target(target) {
+        ("", Some(heading)) => heading.to_string(),
+        (name, Some(heading)) => format!("{} > {}", name, heading),
+        (name, None) => name.to_string(),
+    }
+}
+
+/// Find the file a wiki-link names: a path relative to `base_dir`, or else the closest
+/// file in `base_dir`'s tree whose path ends with the name (`.md` may be left off).
+fn resolve_wikilink(name: &str, base_dir: &Path) -> Option<PathBuf> {
+    let direct = base_dir.join(name);
+    if direct.is_file() {
+        return Some(direct);
+    }
+    let with_md = base_dir.join(format!("{}.md", name));
+    if with_md.is_file() {
+        return Some(with_md);
+    }
+
+    let wanted = name.trim_start_matches("./").replace('\\', "/").to_lowercase();
+    let wanted_md = format!("{}.md", wanted);
+    vault_files(base_dir)
+        .iter()
+        .find(|path| {
+            let relative = path.strip_prefix(base_dir).unwrap_or(path).to_string_lossy().replace('\\', "/").to_lowercase();
+            [&wanted, &wanted_md].iter().any(|w| relative == **w || relative.ends_with(&format!("/{}", w)))
+        })
+        .cloned()
+}
+
+/// Files under `base_dir` (see [`list_vault_files`]), listed once and kept until
+/// [`forget_vault_files`].
+fn vault_files(base_dir: &Path) -> Arc<Vec<PathBuf>> {
+    let Ok(mut listings) = vault_listings().lock() else { return Arc::new(list_vault_files(base_dir)) };
+    listings.entry(base_dir.to_path_buf())
+        .or_insert_with(|| Arc::new(list_vault_files(base_dir)))
+        .clone()
+}
+
+fn vault_listings() -> &'static Mutex<HashMap<PathBuf, Arc<Vec<PathBuf>>>> {
+    static LISTINGS: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<PathBuf>>>>> = OnceLock::new();
+    LISTINGS.get_or_init(Default::default)
+}
+
+/// Drop the cached file listings that wiki-links are resolved against, after files were
+/// added, removed or renamed.
+pub fn forget_vault_files() {
+    if let Ok(mut listings) = vault_listings().lock() {
+        listings.clear();
+    }
+}
+
+/// Files under `base_dir`, shallowest first, skipping hidden and build directories.
+fn list_vault_files(base_dir: &Path) -> Vec<PathBuf> {
+    const MAX_FILES: usize = 20_000;
+    let mut files = Vec::new();
+    let mut dirs = VecDeque::from([base_dir.to_path_buf()]);
+    while let Some(dir) = dirs.pop_front() {
+        let Ok(entries) = std::fs::read_dir(&dir) else { continue };
+        let mut entries: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|e| e.path())).collect();
+        entries.sort();
+        for path in entries {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            if name.starts_with('.') || name == "node_modules" || name == "target" {
+                continue;
+            }
+            if path.is_dir() {
+                dirs.push_back(path);
+            } else {
+                files.push(path);
+            }
+        }
+        if files.len() >= MAX_FILES {
+            break;
+        }
+    }
+    files
+}
+
+/// `file://` URL for a resolved note, with the heading (if any) as the fragment.
+fn wikilink_href(path: &Path, heading: Option<&str>) -> String {
+    let mut href = format!("file://{}", percent_encode(&path.to_string_lossy()));
+    if let Some(heading) = heading {
+        href.push('#');
+        href.push_str(&percent_encode(heading));
+    }
+    href
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Opening `<a>` for a wiki-link: same-document headings become anchors, notes found
+/// under `base_dir` become `file://` links, anything else is marked as broken.
+fn wikilink_open_tag(target: &str, base_dir: Option<&Path>) -> String {
+    match split_wikilink_target(target) {
+        ("", Some(heading)) => format!(r##"<a href="#{}" class="wikilink">"##, slugify(heading)),
+        ("", None) => r#"<a class="wikilink">"#.to_string(),
+        (name, heading) => match base_dir.and_then(|base| resolve_wikilink(name, base)) {
+            Some(path) => format!(r#"<a href="{}" class="wikilink">"#, wikilink_href(&path, heading)),
+            None => format!(r#"<a class="wikilink wikilink-broken" title="Not found: {}">"#, html_escape(name)),
+        },
+    }
+}
+
+/// HTML for `![[Target]]`: an inlined image, a transcluded note (or one of its sections),
+/// a plain link for other files, or a broken marker. Also says whether it is block-level.
+fn wikilink_embed(
+    target: &str,
+    alias: &str,
+    base_dir: Option<&Path>,
+    config: &DirConfig,
+    line: usize,
+) -> (String, bool) {
+    let (name, heading) = split_wikilink_target(target);
+    let Some(path) = base_dir.and_then(|base| resolve_wikilink(name, base)) else {
+        return (
+            format!(r#"<span class="wikilink wikilink-broken" title="Not found: {}">{}</span>"#, html_escape(name), html_escape(&wikilink_label(target))),
+            false,
+        );
+    };
+
+    let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "png" | "jpg" | "jpeg" | "gif" | "svg" | "webp" | "bmp" | "ico" => {
+            let src = resolve_image_url(&path.to_string_lossy(), base_dir);
+            // `![[image.png|300]]` / `|300x200` sets the size, anything else is alt text
+            let size = alias.split_once('x').unwrap_or((alias, ""));
+            let attrs = match (size.0.parse::<u32>(), size.1.parse::<u32>()) {
+                (Ok(width), Ok(height)) => format!(r#" width="{}" height="{}""#, width, height),
+                (Ok(width), Err(_)) if size.1.is_empty() => format!(r#" width="{}""#, width),
+                _ => format!(r#" alt="{}""#, html_escape(alias)),
+            };
+            (format!(r#"<img src="{}" class="wikilink-embed-image"{} />"#, src, attrs), false)
+        }
+        "md" | "markdown" => {
+            let nested = EMBED_STACK.with(|stack| {
+                let stack = stack.borrow();
+                stack.len() >= MAX_EMBED_DEPTH || stack.contains(&path)
+            });
+            let content = std::fs::read_to_string(&path).ok();
+            let (Some(content), false) = (content, nested) else {
+                return (format!(r#"<a href="{}" class="wikilink">{}</a>"#, wikilink_href(&path, heading), html_escape(&wikilink_label(target))), false);
+            };
+            let body = match heading {
+                Some(heading) => note_section(&content, heading).unwrap_or_default(),
+                None => front_matter_block(&content).map_or(content.as_str(), |(_, _, _, body_start)| &content[body_start..]),
+            };
+
+            EMBED_STACK.with(|stack| stack.borrow_mut().push(path.clone()));
+            let inner = markdown_to_html_with_slugger(body, path.parent(), config, &mut Slugger::default());
+            EMBED_STACK.with(|stack| stack.borrow_mut().pop());
+
+            let title = match heading {
+                Some(heading) => format!("{} > {}", note_name(&path), heading),
+                None => note_name(&path),
+            };
+            (
+                format!(
+                    "<div class=\"wikilink-embed\" data-lines=\"{line}-{line}\">\n<div class=\"wikilink-embed-title\"><a href=\"{}\" class=\"wikilink\">{}</a></div>\n{}</div>\n",
+                    wikilink_href(&path, heading), html_escape(&title), strip_heading_ids(&strip_data_lines(&inner)), line = line
+                ),
+                true,
+            )
+        }
+        _ => (format!(r#"<a href="{}" class="wikilink">{}</a>"#, wikilink_href(&path, None), html_escape(&wikilink_label(target))), false),
+    }
+}
+
+fn note_name(path: &Path) -> String {
+    path.file_stem().and_then(|s| s.to_str()).unwrap_or("").to_string()
+}
+
+/// The lines from `heading` up to the next heading of the same or a higher level.
+fn note_section<'a>(content: &'a str, heading: &str) -> Option<&'a str> {
+    let toc = extract_toc(content);
+    let slug = slugify(heading);
+    let index = toc.iter().position(|entry| entry.text.eq_ignore_ascii_case(heading) || entry.slug == slug)?;
+    let entry = &toc[index];
+    let start = line_start(content, entry.line);
+    let end = toc[index + 1..].iter()
+        .find(|next| next.level <= entry.level)
+        .map_or(content.len(), |next| line_start(content, next.line));
+    Some(&content[start..end])
+}
+
+/// Byte offset where 1-based `line` starts.
+fn line_start(content: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0;
+    }
+    content.match_indices('\n').nth(line - 2).map_or(content.len(), |(i, _)| i + 1)
+}
+
+/// Transcluded HTML comes from another file, so its line ranges must not feed smart copy.
+fn strip_data_lines(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(pos) = rest.find(" data-lines=\"") {
+        out.push_str(&rest[..pos]);
+        let after = &rest[pos + 13..];
+        rest = after.find('"').map_or("", |end| &after[end + 1..]);
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Transcluded headings would repeat the host document's ids and catch its `#anchor`
+/// links, so they lose their `id`.
+fn strip_heading_ids(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(pos) = rest.find(" id=\"") {
+        let tag = rest[..pos].rfind('<').map_or("", |start| &rest[start + 1..pos]);
+        let is_heading = tag.len() == 2 && tag.starts_with('h') && matches!(tag.as_bytes()[1], b'1'..=b'6');
+        let after = &rest[pos + 5..];
+        if is_heading {
+            out.push_str(&rest[..pos]);
+            rest = after.find('"').map_or("", |end| &after[end + 1..]);
+        } else {
+            out.push_str(&rest[..pos + 5]);
+            rest = after;
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Turn bare URLs (`https://…`, `www.…`) and email addresses into link events, like
+/// GitHub's extended autolinks; with a `repo_url` configured, `#123` and `owner/repo#123`
+/// too. Code and the text of existing links are left alone. The new events keep the
+/// source range of the text they replace, so `data-lines` don't change.
+fn autolink_events<'a>(events: Vec<(Event<'a>, Range<usize>)>, config: &DirConfig) -> Vec<(Event<'a>, Range<usize>)> {
+    let mut out = Vec::with_capacity(events.len());
+    // pulldown splits text at `_`, `*` and the like; collect adjacent text events
+    let mut run: Vec<(Event<'a>, Range<usize>)> = Vec::new();
+    let mut in_code_block = false;
+    let mut link_depth = 0;
+
+    for (event, range) in events {
+        if matches!(event, Event::Text(_)) && !in_code_block && link_depth == 0 {
+            run.push((event, range));
+            continue;
+        }
+        split_autolinks(std::mem::take(&mut run), config, &mut out);
+        match event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Start(Tag::Link { .. }) | Event::Start(Tag::Image { .. }) => link_depth += 1,
+            Event::End(TagEnd::Link) | Event::End(TagEnd::Image) => link_depth -= 1,
+            _ => {}
+        }
+        out.push((event, range));
+    }
+    split_autolinks(run, config, &mut out);
+    out
+}
+
+fn split_autolinks<'a>(run: Vec<(Event<'a>, Range<usize>)>, config: &DirConfig, out: &mut Vec<(Event<'a>, Range<usize>)>) {
+    let (Some(first), Some(last)) = (run.first(), run.last()) else { return };
+    let range = first.1.start..last.1.end;
+    let text: String = run.iter()
+        .filter_map(|(event, _)| match event {
+            Event::Text(text) => Some(text.as_ref()),
+            _ => None,
+        })
+        .collect();
+    let links = find_autolinks(&text, config);
+    if links.is_empty() {
+        out.extend(run);
+        return;
+    }
+
+    let text_event = |text: &str| (Event::Text(CowStr::from(text.to_string())), range.clone());
+    let mut pos = 0;
+    for (link, href) in links {
+        if link.start > pos {
+            out.push(text_event(&text[pos..link.start]));
+        }
+        let dest_url = CowStr::from(href);
+        out.push((Event::Start(Tag::Link { link_type: LinkType::Autolink, dest_url, title: "".into(), id: "".into() }), range.clone()));
+        out.push(text_event(&text[link.clone()]));
+        out.push((Event::End(TagEnd::Link), range.clone()));
+        pos = link.end;
+    }
+    if pos < text.len() {
+        out.push(text_event(&text[pos..]));
+    }
+}
+
+/// Byte ranges of the autolinks in `text`, with their hrefs.
+fn find_autolinks(text: &str, config: &DirConfig) -> Vec<(Range<usize>, String)> {
+    let mut links = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        let prev = text[..i].chars().next_back();
+        let rest = &text[i..];
+        // Like GitHub, links start after whitespace, `(` or emphasis delimiters
+        let at_boundary = match prev {
+            Some(c) => c.is_whitespace() || matches!(c, '(' | '*' | '_' | '~'),
+            None => true,
+        };
+        let found = at_boundary
+            .then(|| match_url(rest).or_else(|| match_issue_ref(rest, config)))
+            .flatten()
+            .or_else(|| (!prev.is_some_and(is_email_char)).then(|| match_email(rest)).flatten());
+        match found {
+            Some((len, href)) => {
+                links.push((i..i + len, href));
+                i += len;
+            }
+            // Not a valid URL, but no part of it is an email address either
+            None if at_boundary && starts_url(rest) => {
+                i += rest.find(char::is_whitespace).unwrap_or(rest.len());
+            }
+            None => i += rest.chars().next().map_or(1, char::len_utf8),
+        }
+    }
+    links
+}
+
+/// Length of the `https://`, `http://` or `www.` that `text` starts with, and the scheme
+/// its href needs.
+fn url_prefix(text: &str) -> Option<(usize, &'static str)> {
+    let lower = text.get(..8).unwrap_or(text).to_ascii_lowercase();
+    if lower.starts_with("https://") {
+        Some((8, ""))
+    } else if lower.starts_with("http://") {
+        Some((7, ""))
+    } else if lower.starts_with("www.") {
+        Some((4, "http://"))
+    } else {
+        None
+    }
+}
+
+fn starts_url(text: &str) -> bool {
+    url_prefix(text).is_some()
+}
+
+/// `https://…`, `http://…` or `www.…` at the start of `text`: (length, href).
+fn match_url(text: &str) -> Option<(usize, String)> {
+    let (prefix_len, scheme) = url_prefix(text)?;
+
+    let end = text.find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"')).unwrap_or(text.len());
+    let mut url = &text[..end];
+
+    // Trailing punctuation belongs to the sentence, as do unbalanced `)` and entity-like `&amp;`
+    while let Some(last) = url.chars().next_back() {
+        let unbalanced = last == ')' && url.matches('(').count() < url.matches(')').count();
+        if unbalanced || matches!(last, '?' | '!' | '.' | ',' | ':' | '*' | '_' | '~' | '\'') {
+            url = &url[..url.len() - 1];
+        } else if last == ';' {
+            match url.rfind('&') {
+                Some(amp) if amp + 1 < url.len() - 1 && url[amp + 1..url.len() - 1].chars().all(|c| c.is_ascii_alphanumeric()) => url = &url[..amp],
+                _ => break,
+            }
+        } else {
+            break;
+        }
+    }
+    if url.len() <= prefix_len {
+        return None;
+    }
+
+    // The host comes after any `user@` and before any `:port`
+    let authority_end = url[prefix_len..].find(['/', '?', '#']).map_or(url.len(), |i| prefix_len + i);
+    let authority = &url[prefix_len..authority_end];
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    if host.is_empty() || !host.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.')) {
+        return None;
+    }
+    Some((url.len(), format!("{}{}", scheme, url)))
+}
+
+fn is_email_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-' | '_')
+}
+
+/// `name@example.com` at the start of `text`: (length, `mailto:` href).
+fn match_email(text: &str) -> Option<(usize, String)> {
+    let at = text.find(|c: char| !is_email_char(c))?;
+    if at == 0 || !text[at..].starts_with('@') {
+        return None;
+    }
+    let domain_start = at + 1;
+    let domain_len = text[domain_start..]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+        .unwrap_or(text.len() - domain_start);
+    let domain = text[domain_start..domain_start + domain_len].trim_end_matches('.');
+    if !domain.contains('.') || domain.starts_with('.') || domain.ends_with(['-', '_']) {
+        return None;
+    }
+    let len = domain_start + domain.len();
+    Some((len, format!("mailto:{}", &text[..len])))
+}
+
+/// `#123` or `owner/repo#123` at the start of `text`, linked to the configured repository's
+/// issues (GitHub redirects pull request numbers): (length, href).
+fn match_issue_ref(text: &str, config: &DirConfig) -> Option<(usize, String)> {
+    let repo_url = config.repo_url.as_deref()?.trim_end_matches('/');
+    let hash = text.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')))?;
+    let (repo, number) = (&text[..hash], text[hash..].strip_prefix('#')?);
+    let digits = number.find(|c: char| !c.is_ascii_digit()).unwrap_or(number.len());
+    if digits == 0 || number[digits..].starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let base = if repo.is_empty() {
+        repo_url.to_string()
+    } else {
+        let (owner, name) = repo.split_once('/')?;
+        let valid = |part: &str| !part.is_empty() && !part.starts_with('.') && !part.contains('/');
+        if !valid(owner) || !valid(name) {
+            return None;
+        }
+        format!("{}/{}", config.repo_host()?, repo)
+    };
+    let len = hash + 1 + digits;
+    Some((len, format!("{}/issues/{}", base, &number[..digits])))
+}
+
+/// Render markdown (GFM tables, task lists, strikethrough, footnotes, alerts, math) to an HTML
+/// fragment. Front matter becomes a collapsed metadata card.
+///
+/// Every block element carries a `data-lines="start-end"` attribute with the 1-based,
+/// inclusive source line range it came from; smart copy uses these to map a selection
+/// back to markdown. Relative images are resolved against `base_dir` and inlined.
+pub fn markdown_to_html(markdown: &str, base_dir: Option<&Path>) -> String {
+    markdown_to_html_with_slugger(markdown, base_dir, &DirConfig::load(base_dir), &mut Slugger::default())
+}
+
+/// [`markdown_to_html`] with the directory `config` loaded once by the caller, and heading
+/// ids taken from a shared `slugger` (notebook cells).
+pub fn markdown_to_html_with_slugger(markdown: &str, base_dir: Option<&Path>, config: &DirConfig, slugger: &mut Slugger) -> String {
+    let front_matter = parse_front_matter(markdown);
+    let source = prepare_source(markdown);
+    let markdown: &str = &source;
+    let parser = autolink_events(
+        wikilink_events(markdown, Parser::new_ext(markdown, parser_options()).into_offset_iter()),
+        config,
+    );
+    let mut html_output = front_matter.as_ref().map(front_matter_html).unwrap_or_default();
+    html_output.push_str(&katex_macros_html(front_matter.as_ref(), config));
+
+    // Track current block's line range
+    let mut block_start_line: Option<usize> = None;
+    let mut pending_block_tag: Option<String> = None;
+
+    // Heading-specific tracking: collect content and plain text for slug
+    let mut in_heading: Option<String> = None; // The heading tag (h1, h2, etc.)
+    let mut heading_start_line: usize = 0;
+    let mut heading_html_content = String::new();
+    let mut heading_plain_text = String::new();
+    // Explicit `{#id .class}` attributes of the current heading
+    let mut heading_id: Option<String> = None;
+    let mut heading_classes: Vec<String> = Vec::new();
+
+    // Stack to handle nested elements
+    let mut tag_stack: Vec<String> = Vec::new();
+
+    // Column alignments of the current table, and the index of the next cell in its row
+    let mut table_alignments: Vec<Alignment> = Vec::new();
+    let mut table_cell_index = 0;
+
+    // Footnotes are numbered in order of first reference; definitions are rendered into
+    // their own buffer and emitted together at the end
+    let mut footnote_refs: HashMap<String, (usize, usize)> = HashMap::new(); // name -> (number, reference count)
+    let mut footnote_defs: HashMap<String, (String, usize, usize)> = HashMap::new(); // name -> (html, start, end)
+    let mut footnote_outer: Option<(String, String, usize)> = None; // (name, document html so far, start line)
+
+    // Byte range of an Obsidian callout's `[!type] Title` marker; its events are skipped
+    let mut callout_marker: Option<(usize, usize)> = None;
+
+    // Fenced block with a configured renderer: (command, source so far, start line)
+    let mut external_block: Option<(String, String, usize)> = None;
+    // Fenced block highlighted here: (language, source so far)
+    let mut code_block: Option<(String, String)> = None;
+
+    // A note embed stands in for the image events up to End(Image), and for the
+    // paragraph when it is the paragraph's first content
+    let mut in_embed = false;
+    let mut paragraph_replaced = false;
+
+    for (event, range) in parser {
+        let mut start_line = byte_offset_to_line(markdown, range.start);
+        let end_line = byte_offset_to_line(markdown, range.end);
+
+        if in_embed {
+            in_embed = !matches!(event, Event::End(TagEnd::Image));
+            continue;
+        }
+
+        if let Some((marker_start, marker_end)) = callout_marker {
+            if range.start >= marker_start && range.end <= marker_end + 1 {
+                continue;
+            }
+            if range.start > marker_end {
+                callout_marker = None;
+            } else {
+                // The first paragraph begins on the line after the marker
+                start_line = byte_offset_to_line(markdown, marker_end + 1);
+            }
+        }
+
+        match event {
+            Event::Start(Tag::Paragraph) => {
+                block_start_line = Some(start_line);
+                pending_block_tag = Some("p".to_string());
+                tag_stack.push("p".to_string());
+            }
+            Event::End(TagEnd::Paragraph) => {
+                if let (Some(start), Some(_)) = (block_start_line, &pending_block_tag) {
+                    html_output.push_str(&format!(r#"<p data-lines="{}-{}">"#, start, end_line));
+                }
+                if !std::mem::take(&mut paragraph_replaced) {
+                    html_output.push_str("</p>\n");
+                }
+                block_start_line = None;
+                pending_block_tag = None;
+                tag_stack.pop();
+            }
+
+            Event::Start(Tag::Heading { level, id, classes, .. }) => {
+                let tag = level.to_string();
+                in_heading = Some(tag.clone());
+                heading_start_line = start_line;
+                heading_html_content.clear();
+                heading_plain_text.clear();
+                heading_id = id.map(|id| id.to_string());
+                heading_classes = classes.iter().map(|class| class.to_string()).collect();
+                tag_stack.push(tag);
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                let tag = level.to_string();
+                let slug = slugger.heading_id(&heading_plain_text, heading_id.take().as_deref());
+                let class_attr = if heading_classes.is_empty() {
+                    String::new()
+                } else {
+                    format!(r#" class="{}""#, html_escape(&heading_classes.join(" ")))
+                };
+                html_output.push_str(&format!(
+                    r#"<{} id="{}"{} data-lines="{}-{}">{}</{}>"#,
+                    tag, html_escape(&slug), class_attr, heading_start_line, end_line, heading_html_content, tag
+                ));
+                html_output.push('\n');
+                in_heading = None;
+                tag_stack.pop();
+            }
+
+            Event::Start(Tag::BlockQuote(kind)) => {
+                let callout = match kind {
+                    Some(kind) => Some(Callout::from_alert(kind)),
+                    None => parse_callout(markdown, range.start).map(|(callout, marker)| {
+                        callout_marker = Some(marker);
+                        callout
+                    }),
+                };
+                match callout {
+                    Some(callout) => {
+                        html_output.push_str(&callout.open_html(start_line));
+                        html_output.push('\n');
+                        tag_stack.push(if callout.fold.is_some() { "details" } else { "div" }.to_string());
+                    }
+                    None => {
+                        html_output.push_str(&format!(r#"<blockquote data-lines="{}-__BQ_END__">"#, start_line));
+                        tag_stack.push("blockquote".to_string());
+                    }
+                }
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                if let Some(pos) = html_output.rfind("__BQ_END__") {
+                    html_output.replace_range(pos..pos + 10, &end_line.to_string());
+                }
+                let tag = tag_stack.pop().unwrap_or_default();
+                html_output.push_str(&format!("</{}>\n", if tag.is_empty() { "blockquote" } else { &tag }));
+            }
+
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let info = match &kind {
+                    CodeBlockKind::Fenced(info) => parse_code_info(info),
+                    CodeBlockKind::Indented => CodeInfo::default(),
+                };
+                let lang = info.lang;
+                if let Some(command) = lang.and_then(renderer_for) {
+                    // Rendered by an external command once the whole source is collected
+                    external_block = Some((command, String::new(), start_line));
+                    tag_stack.push("external".to_string());
+                } else if lang == Some("math") {
+                    // Math block - typeset by KaTeX like display math
+                    html_output.push_str(&format!(r#"<div class="math-block math-display" data-lines="{}-__MATH_END__">"#, start_line));
+                    tag_stack.push("math".to_string());
+                } else if lang == Some("mermaid") {
+                    // Diagram source - rendered to SVG by mermaid.js
+                    html_output.push_str(&format!(r#"<div class="mermaid" data-lines="{}-__MERMAID_END__">"#, start_line));
+                    tag_stack.push("mermaid".to_string());
+                } else {
+                    html_output.push_str(&format!(r#"<pre data-lines="{}-__PRE_END__"{}>"#, start_line, info.pre_attributes()));
+                    if let Some(title) = &info.title {
+                        html_output.push_str(&format!(r#"<div class="code-header code-title">{}</div>"#, html_escape(title)));
+                    }
+                    match lang {
+                        Some(lang) => {
+                            // Highlighted at the end of the block (in the page if there's no grammar)
+                            let highlighted = find_syntax(lang).is_some();
+                            html_output.push_str(&format!(r#"<code class="language-{}{}">"#, html_escape(lang), if highlighted { " hljs" } else { "" }));
+                            if highlighted {
+                                code_block = Some((lang.to_string(), String::new()));
+                            }
+                        }
+                        None => html_output.push_str("<code>"),
+                    }
+                    tag_stack.push("pre".to_string());
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let tag_type = tag_stack.pop().unwrap_or_default();
+                if let Some((command, source, start)) = external_block.take() {
+                    // Add 1 to include the closing ``` fence line
+                    html_output.push_str(&external_block_html(&command, &source, start, end_line + 1));
+                } else if tag_type == "math" {
+                    html_output.push_str("</div>\n");
+                    if let Some(pos) = html_output.rfind("__MATH_END__") {
+                        html_output.replace_range(pos..pos + 12, &(end_line + 1).to_string());
+                    }
+                } else if tag_type == "mermaid" {
+                    html_output.push_str("</div>\n");
+                    if let Some(pos) = html_output.rfind("__MERMAID_END__") {
+                        html_output.replace_range(pos..pos + 15, &(end_line + 1).to_string());
+                    }
+                } else {
+                    if let Some((lang, source)) = code_block.take() {
+                        html_output.push_str(&highlight_code_html(&source, &lang).unwrap_or_else(|| html_escape(&source)));
+                    }
+                    html_output.push_str("</code></pre>\n");
+                    if let Some(pos) = html_output.rfind("__PRE_END__") {
+                        // Add 1 to include the closing ``` fence line
+                        html_output.replace_range(pos..pos + 11, &(end_line + 1).to_string());
+                    }
+                }
+            }
+
+            Event::Start(Tag::List(first_item)) => {
+                if first_item.is_some() {
+                    html_output.push_str(&format!(r#"<ol data-lines="{}-__OL_END__">"#, start_line));
+                    tag_stack.push("ol".to_string());
+                } else {
+                    html_output.push_str(&format!(r#"<ul data-lines="{}-__UL_END__">"#, start_line));
+                    tag_stack.push("ul".to_string());
+                }
+            }
+            Event::End(TagEnd::List(ordered)) => {
+                let (tag, placeholder) = if ordered { ("ol", "__OL_END__") } else { ("ul", "__UL_END__") };
+                if let Some(pos) = html_output.rfind(placeholder) {
+                    html_output.replace_range(pos..pos + placeholder.len(), &end_line.to_string());
+                }
+                html_output.push_str(&format!("</{}>", tag));
+                tag_stack.pop();
+            }
+
+            Event::Start(Tag::Item) => {
+                html_output.push_str(&format!(r#"<li data-lines="{}-__LI_END__">"#, start_line));
+                tag_stack.push("li".to_string());
+            }
+            Event::End(TagEnd::Item) => {
+                if let Some(pos) = html_output.rfind("__LI_END__") {
+                    html_output.replace_range(pos..pos + 10, &end_line.to_string());
+                }
+                html_output.push_str("</li>\n");
+                tag_stack.pop();
+            }
+
+            Event::Start(Tag::Table(alignments)) => {
+                table_alignments = alignments;
+                // Use placeholder for end line, replace when table ends
+                html_output.push_str(&format!(r#"<table data-lines="{}-__TABLE_END__">"#, start_line));
+                tag_stack.push("table".to_string());
+            }
+            Event::End(TagEnd::Table) => {
+                // Replace the placeholder with actual end line
+                if let Some(pos) = html_output.rfind("__TABLE_END__") {
+                    html_output.replace_range(pos..pos + 13, &end_line.to_string());
+                }
+                html_output.push_str("</table>\n");
+                tag_stack.pop();
+            }
+            Event::Start(Tag::TableHead) => {
+                table_cell_index = 0;
+                html_output.push_str("<thead><tr>");
+                tag_stack.push("thead".to_string());
+            }
+            Event::End(TagEnd::TableHead) => {
+                html_output.push_str("</tr></thead>");
+                tag_stack.pop();
+            }
+            Event::Start(Tag::TableRow) => {
+                table_cell_index = 0;
+                html_output.push_str("<tr>");
+            }
+            Event::End(TagEnd::TableRow) => {
+                html_output.push_str("</tr>");
+            }
+            Event::Start(Tag::TableCell) => {
+                // Use <th> in thead, <td> elsewhere
+                let cell = if tag_stack.iter().any(|t| t == "thead") { "th" } else { "td" };
+                let style = match table_alignments.get(table_cell_index) {
+                    Some(Alignment::Left) => r#" style="text-align: left""#,
+                    Some(Alignment::Center) => r#" style="text-align: center""#,
+                    Some(Alignment::Right) => r#" style="text-align: right""#,
+                    _ => "",
+                };
+                html_output.push_str(&format!("<{}{}>", cell, style));
+                table_cell_index += 1;
+            }
+            Event::End(TagEnd::TableCell) => {
+                if tag_stack.iter().any(|t| t == "thead") {
+                    html_output.push_str("</th>");
+                } else {
+                    html_output.push_str("</td>");
+                }
+            }
+
+            // Inline elements - route to heading buffer if inside a heading
+            Event::Start(Tag::Emphasis) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("<em>");
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str("<em>");
+                }
+            }
+            Event::End(TagEnd::Emphasis) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("</em>");
+                } else {
+                    html_output.push_str("</em>");
+                }
+            }
+            Event::Start(Tag::Strong) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("<strong>");
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str("<strong>");
+                }
+            }
+            Event::End(TagEnd::Strong) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("</strong>");
+                } else {
+                    html_output.push_str("</strong>");
+                }
+            }
+            Event::Start(Tag::Strikethrough) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("<del>");
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str("<del>");
+                }
+            }
+            Event::End(TagEnd::Strikethrough) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("</del>");
+                } else {
+                    html_output.push_str("</del>");
+                }
+            }
+            Event::Start(Tag::Link { dest_url, title, .. }) => {
+                let link_html = if let Some(target) = dest_url.strip_prefix(WIKILINK_PREFIX) {
+                    wikilink_open_tag(target, base_dir)
+                } else if title.is_empty() {
+                    format!(r#"<a href="{}">"#, dest_url)
+                } else {
+                    format!(r#"<a href="{}" title="{}">"#, dest_url, title)
+                };
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&link_html);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&link_html);
+                }
+            }
+            Event::End(TagEnd::Link) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("</a>");
+                } else {
+                    html_output.push_str("</a>");
+                }
+            }
+            Event::Start(Tag::Image { dest_url, title, .. }) if dest_url.starts_with(WIKILINK_PREFIX) => {
+                let target = &dest_url[WIKILINK_PREFIX.len()..];
+                let (embed_html, is_block) = wikilink_embed(target, &title, base_dir, config, start_line);
+                in_embed = true;
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&embed_html);
+                } else if is_block && pending_block_tag.is_some() {
+                    // A note embedded on its own line takes the place of the paragraph
+                    pending_block_tag = None;
+                    paragraph_replaced = true;
+                    html_output.push_str(&embed_html);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&embed_html);
+                }
+            }
+            Event::Start(Tag::Image { dest_url, title, .. }) => {
+                let resolved_url = resolve_image_url(&dest_url, base_dir);
+                let mut img_html = format!(r#"<img src="{}" alt=""#, resolved_url);
+                if !title.is_empty() {
+                    img_html.push_str(&format!(r#"" title="{}""#, title));
+                }
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&img_html);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&img_html);
+                }
+            }
+            Event::End(TagEnd::Image) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str(r#"" />"#);
+                } else {
+                    html_output.push_str(r#"" />"#);
+                }
+            }
+
+            Event::Text(text) if external_block.is_some() || code_block.is_some() => {
+                if let Some((_, source, _)) = external_block.as_mut() {
+                    source.push_str(&text);
+                } else if let Some((_, source)) = code_block.as_mut() {
+                    source.push_str(&text);
+                }
+            }
+            Event::Text(text) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&html_escape(&text));
+                    heading_plain_text.push_str(&text);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&html_escape(&text));
+                }
+            }
+            Event::Code(code) => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&format!("<code>{}</code>", html_escape(&code)));
+                    heading_plain_text.push_str(&code);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&format!("<code>{}</code>", html_escape(&code)));
+                }
+            }
+            Event::InlineMath(ref math) | Event::DisplayMath(ref math) => {
+                // TeX source, typeset by KaTeX in the page
+                let class = if matches!(event, Event::DisplayMath(_)) { "math math-display" } else { "math math-inline" };
+                let math_html = format!(r#"<span class="{}">{}</span>"#, class, html_escape(math));
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&math_html);
+                    heading_plain_text.push_str(math);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&math_html);
+                }
+            }
+            Event::SoftBreak => {
+                if in_heading.is_some() {
+                    heading_html_content.push('\n');
+                } else {
+                    html_output.push('\n');
+                }
+            }
+            Event::HardBreak => {
+                if in_heading.is_some() {
+                    heading_html_content.push_str("<br />\n");
+                } else {
+                    html_output.push_str("<br />\n");
+                }
+            }
+            Event::Rule => {
+                html_output.push_str(&format!(r#"<hr data-lines="{}-{}" />"#, start_line, end_line));
+            }
+
+            Event::Html(html) => {
+                html_output.push_str(&html);
+            }
+
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                footnote_outer = Some((name.to_string(), std::mem::take(&mut html_output), start_line));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                if let Some((name, outer, start)) = footnote_outer.take() {
+                    let body = std::mem::replace(&mut html_output, outer);
+                    // The definition's range includes the blank lines after it
+                    let content_end = range.start + markdown[range.clone()].trim_end().len();
+                    let end = byte_offset_to_line(markdown, content_end).max(start);
+                    footnote_defs.insert(name, (body, start, end));
+                }
+            }
+            Event::FootnoteReference(name) => {
+                let next_number = footnote_refs.len() + 1;
+                let (number, count) = footnote_refs.entry(name.to_string()).or_insert((next_number, 0));
+                *count += 1;
+                let sup = format!(
+                    r##"<sup class="footnote-ref"><a href="#fn-{}" id="{}">{}</a></sup>"##,
+                    footnote_id(&name), footnote_ref_id(&name, *count), number
+                );
+                if in_heading.is_some() {
+                    heading_html_content.push_str(&sup);
+                } else {
+                    if pending_block_tag.is_some() {
+                        flush_pending_tag(&mut html_output, &pending_block_tag, block_start_line, end_line);
+                        pending_block_tag = None;
+                    }
+                    html_output.push_str(&sup);
+                }
+            }
+
+            Event::TaskListMarker(checked) => {
+                if checked {
+                    html_output.push_str(r#"<input type="checkbox" checked disabled /> "#);
+                } else {
+                    html_output.push_str(r#"<input type="checkbox" disabled /> "#);
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    if !footnote_refs.is_empty() {
+        html_output.push_str(&build_footnotes_section(&footnote_refs, &footnote_defs));
+    }
+
+    html_output
+}
+
+/// GitHub-style footnotes list: definitions in reference order, each with ↩ links back to
+/// every reference. Definitions that are never referenced are left out, as on GitHub.
+fn build_footnotes_section(refs: &HashMap<String, (usize, usize)>, defs: &HashMap<String, (String, usize, usize)>) -> String {
+    let mut notes: Vec<(&String, &(usize, usize))> = refs.iter().collect();
+    notes.sort_by_key(|(_, (number, _))| *number);
+
+    let mut html = String::from("<section class=\"footnotes\">\n<ol>\n");
+    for (name, &(number, count)) in notes {
+        let Some((body, start, end)) = defs.get(name) else { continue };
+        let backrefs: String = (1..=count)
+            .map(|k| {
+                let suffix = if k > 1 { format!("<sup>{}</sup>", k) } else { String::new() };
+                format!(
+                    r##" <a href="#{}" class="footnote-backref" aria-label="Back to reference {}">↩{}</a>"##,
+                    footnote_ref_id(name, k), number, suffix
+                )
+            })
+            .collect();
+        // Put the back-links at the end of the last paragraph, like GitHub
+        let mut body = body.trim_end().to_string();
+        match body.strip_suffix("</p>") {
+            Some(inner) => body = format!("{}{}</p>", inner, backrefs),
+            None => body.push_str(&backrefs),
+        }
+        html.push_str(&format!(
+            r#"<li id="fn-{}" value="{}" data-lines="{}-{}">{}</li>"#,
+            footnote_id(name), number, start, end, body
+        ));
+        html.push('\n');
+    }
+    html.push_str("</ol>\n</section>\n");
+    html
+}
+
+/// Anchor-safe form of a footnote label (`[^my note]` -> `my-note`).
+fn footnote_id(name: &str) -> String {
+    html_escape(&name.split_whitespace().collect::<Vec<_>>().join("-"))
+}
+
+/// Id of the `k`-th reference (1-based) to a footnote, target of its ↩ link.
+fn footnote_ref_id(name: &str, k: usize) -> String {
+    if k == 1 {
+        format!("fnref-{}", footnote_id(name))
+    } else {
+        format!("fnref-{}-{}", footnote_id(name), k)
+    }
+}
+
+/// Metadata block at the very top of a file: YAML between `---` lines or TOML between `+++`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontMatter {
+    /// Keys in source order with their values as written, minus string quotes. Nested
+    /// YAML values keep their raw (dedented) text; keys in TOML tables become `table.key`.
+    pub fields: Vec<(String, String)>,
+    /// 1-based source line of the closing delimiter
+    pub end_line: usize,
+}
+
+impl FrontMatter {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// The `title` field, if present and non-empty
+    pub fn title(&self) -> Option<&str> {
+        self.get("title").filter(|title| !title.is_empty())
+    }
+}
+
+/// KaTeX macros for a document: the directory config's, then its front matter's
+/// `katex_macros` (a YAML map or JSON object of `\\name: expansion`, or a TOML
+/// `[katex_macros]` table), passed to the page in a hidden `.katex-macros` element.
+fn katex_macros_html(front_matter: Option<&FrontMatter>, config: &DirConfig) -> String {
+    let mut macros = config.katex_macros.clone();
+    for (key, value) in front_matter.map(|fm| fm.fields.as_slice()).unwrap_or_default() {
+        if key == "katex_macros" {
+            match serde_json::from_str::<BTreeMap<String, String>>(value) {
+                Ok(map) => macros.extend(map),
+                Err(_) => macros.extend(value.lines().filter_map(|line| {
+                    let (name, expansion) = line.split_once(':')?;
+                    Some((name.trim().to_string(), unquote(expansion.trim()).to_string()))
+                })),
+            }
+        } else if let Some(name) = key.strip_prefix("katex_macros.") {
+            macros.insert(name.to_string(), value.clone());
+        }
+    }
+    if macros.is_empty() {
+        return String::new();
+    }
+    let macros: BTreeMap<String, String> = macros.into_iter()
+        .map(|(name, expansion)| if name.starts_with('\\') { (name, expansion) } else { (format!("\\{}", name), expansion) })
+        .collect();
+    format!(
+        r#"<span class="katex-macros" hidden data-macros="{}"></span>"#,
+        html_escape(&serde_json::to_string(&macros).unwrap_or_default())
+    )
+}
+
+/// Parse the front matter at the start of `markdown`, if there is a complete block.
+pub fn parse_front_matter(markdown: &str) -> Option<FrontMatter> {
+    let (toml, body, end_line, _) = front_matter_block(markdown)?;
+    let fields = if toml { parse_toml_fields(body) } else { parse_yaml_fields(body) };
+    Some(FrontMatter { fields, end_line })
+}
+
+/// Locate the front matter block: (is TOML, text between the delimiters, closing line,
+/// byte offset where the document body starts).
+fn front_matter_block(markdown: &str) -> Option<(bool, &str, usize, usize)> {
+    let text = markdown.strip_prefix('\u{feff}').unwrap_or(markdown);
+    let offset = markdown.len() - text.len();
+    let first = text.lines().next()?;
+    let toml = match first.trim_end() {
+        "---" => false,
+        "+++" => true,
+        _ => return None,
+    };
+
+    // After the opening line's `\n` (`lines()` has already dropped any `\r`)
+    let body_start = text.find('\n')? + 1;
+    let mut pos = body_start;
+    let mut line = 1;
+    for current in text[body_start..].split_inclusive('\n') {
+        line += 1;
+        let delimiter = current.trim_end();
+        if (toml && delimiter == "+++") || (!toml && (delimiter == "---" || delimiter == "...")) {
+            let body = &text[body_start..pos];
+            return is_metadata(body, toml).then_some((toml, body, line, offset + pos + current.len()));
+        }
+        pos += current.len();
+    }
+    None
+}
+
+/// Whether the text between the delimiters is made of fields, as GitHub requires. Otherwise
+/// the `---` lines are thematic breaks around ordinary markdown.
+fn is_metadata(body: &str, toml: bool) -> bool {
+    let mut lines = body.lines().filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    if toml {
+        let is_field = |line: &str| {
+            let line = line.trim();
+            (line.starts_with('[') && line.ends_with(']')) || line.split_once('=').is_some_and(|(key, _)| !key.trim().is_empty())
+        };
+        return lines.next().is_some_and(is_field);
+    }
+    // Every top-level line is `key: value` (or `key:` with nested lines below)
+    let mut any = false;
+    for line in lines {
+        if line.starts_with([' ', '\t', '-']) {
+            if !any {
+                return false;
+            }
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else { return false };
+        if key.trim().is_empty() || !(value.is_empty() || value.starts_with([' ', '\t'])) {
+            return false;
+        }
+        any = true;
+    }
+    any
+}
+
+/// Top-level `key: value` pairs; indented lines and list items belong to the key above.
+fn parse_yaml_fields(body: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in body.lines() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let nested = line.starts_with([' ', '\t', '-']);
+        match line.split_once(':') {
+            Some((key, value)) if !nested => {
+                fields.push((key.trim().to_string(), unquote(value.trim()).to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    if !value.is_empty() {
+                        value.push('\n');
+                    }
+                    value.push_str(line.strip_prefix("  ").unwrap_or(line));
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// `key = value` pairs, prefixed with the `[table]` they appear in. Lines that continue a
+/// multi-line array or string are appended to the previous value.
+fn parse_toml_fields(body: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut table = String::new();
+    for line in body.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed.starts_with('[') && trimmed.ends_with(']') && !trimmed.contains('=') {
+            table = trimmed.trim_matches(['[', ']']).trim().to_string();
+            continue;
+        }
+        match trimmed.split_once('=') {
+            Some((key, value)) if !key.contains(['"', '\'', '[']) => {
+                let key = key.trim();
+                let key = if table.is_empty() { key.to_string() } else { format!("{}.{}", table, key) };
+                fields.push((key, unquote(value.trim()).to_string()));
+            }
+            _ => {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push('\n');
+                    value.push_str(trimmed);
+                }
+            }
+        }
+    }
+    fields
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+/// Collapsed card listing the front matter fields; its `data-lines` span the whole block.
+fn front_matter_html(front_matter: &FrontMatter) -> String {
+    let mut html = format!(
+        "<details class=\"front-matter\" data-lines=\"1-{}\">\n<summary>Metadata</summary>\n<table>\n<tbody>\n",
+        front_matter.end_line
+    );
+    for (key, value) in &front_matter.fields {
+        html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", html_escape(key), html_escape(value)));
+    }
+    html.push_str("</tbody>\n</table>\n</details>\n");
+    html
+}
+
+/// The source as the parser sees it: front matter blanked out (it is rendered separately),
+/// MkDocs admonitions and `\(…\)` / `\[…\]` math rewritten. Every line keeps its line number.
+pub(crate) fn prepare_source(markdown: &str) -> Cow<'_, str> {
+    let source = match front_matter_block(markdown) {
+        Some((_, _, end_line, body_start)) => {
+            let blanked = "\n".repeat(end_line) + &markdown[body_start..];
+            Cow::Owned(normalize_admonitions(&blanked).into_owned())
+        }
+        None => normalize_admonitions(markdown),
+    };
+    match normalize_math_delimiters(&source) {
+        Cow::Borrowed(_) => source,
+        Cow::Owned(normalized) => Cow::Owned(normalized),
+    }
+}
+
+/// Rewrite LaTeX's `\(…\)` to `$…$` and `\[…\]` to `$$…$$`, which the parser's math
+/// extension understands. Code is left alone, and so are escaped brackets like `\[1\]`:
+/// display math must start a line and end one.
+fn normalize_math_delimiters(markdown: &str) -> Cow<'_, str> {
+    if !markdown.contains("\\(") && !markdown.contains("\\[") {
+        return Cow::Borrowed(markdown);
+    }
+
+    let mut out = String::with_capacity(markdown.len());
+    let mut fence: Option<&str> = None;
+    let mut rest = markdown;
+    while !rest.is_empty() {
+        let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        let trimmed = rest[..line_end].trim();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let first = trimmed.as_bytes()[0];
+            let len = trimmed.bytes().take_while(|&b| b == first).count();
+            fence = Some(&trimmed[..len]);
+        } else {
+            let consumed = convert_math_line(rest, line_end, &mut out);
+            rest = &rest[consumed..];
+            continue;
+        }
+        out.push_str(&rest[..line_end]);
+        rest = &rest[line_end..];
+    }
+    Cow::Owned(out)
+}
+
+/// Copy the line `text[..line_end]` to `out`, converting its math delimiters. Display math
+/// may run over several lines; returns how much of `text` was consumed.
+fn convert_math_line(text: &str, line_end: usize, out: &mut String) -> usize {
+    let bytes = text.as_bytes();
+    let mut code_ticks = 0; // length of the backtick run that opened the current code span
+    let mut i = 0;
+    while i < line_end {
+        if bytes[i] == b'`' {
+            let run = bytes[i..line_end].iter().take_while(|&&b| b == b'`').count();
+            if code_ticks == 0 {
+                code_ticks = run;
+            } else if run == code_ticks {
+                code_ticks = 0;
+            }
+            out.push_str(&text[i..i + run]);
+            i += run;
+            continue;
+        }
+        if bytes[i] == b'\\' && code_ticks == 0 {
+            if let Some((len, math)) = math_at(text, i, line_end) {
+                out.push_str(&math);
+                i += len;
+                if i >= line_end {
+                    return i;
+                }
+                continue;
+            }
+            // Copy escapes whole, so `\\(` stays an escaped backslash
+            let len = 1 + text[i + 1..].chars().next().map_or(0, char::len_utf8);
+            out.push_str(&text[i..i + len]);
+            i += len;
+            continue;
+        }
+        let len = text[i..].chars().next().map_or(1, char::len_utf8);
+        out.push_str(&text[i..i + len]);
+        i += len;
+    }
+    i
+}
+
+/// `\(…\)` or `\[…\]` starting at `text[start..]`: (length, rewritten math).
+fn math_at(text: &str, start: usize, line_end: usize) -> Option<(usize, String)> {
+    let rest = &text[start..];
+    if let Some(inner) = rest[..line_end - start].strip_prefix("\\(") {
+        // `foo\(bar\)` is an escaped call in prose, not math
+        if text[..start].chars().next_back().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            return None;
+        }
+        let close = inner.find("\\)")?;
+        let math = inner[..close].trim();
+        return (!math.is_empty()).then(|| (close + 4, format!("${}$", math)));
+    }
+
+    let inner = rest.strip_prefix("\\[")?;
+    if !text[..start].rsplit('\n').next().unwrap_or("").trim().is_empty() {
+        return None;
+    }
+    // Display math stays within its paragraph
+    let paragraph = inner.find("\n\n").map_or(inner, |end| &inner[..end + 1]);
+    let close = paragraph.find("\\]")?;
+    let after = &paragraph[close + 2..];
+    let after_len = after.find('\n').map_or(after.len(), |i| i + 1);
+    if !after[..after_len].trim().is_empty() || paragraph[..close].trim().is_empty() {
+        return None;
+    }
+    // The `\]` line's trailing whitespace and newline are consumed and re-emitted
+    Some((2 + close + 2 + after_len, format!("$${}$${}", &paragraph[..close], &after[..after_len])))
+}
+
+/// A GitHub alert (`> [!NOTE]`), Obsidian callout (`> [!info]- Title`) or MkDocs
+/// admonition, rendered as a titled box in one of GitHub's five alert styles.
+struct Callout {
+    kind: &'static str,
+    title: String,
+    /// `Some(open)` for collapsible callouts (`+` or `-` after the type)
+    fold: Option<bool>,
+}
+
+impl Callout {
+    fn from_alert(kind: BlockQuoteKind) -> Self {
+        let kind = match kind {
+            BlockQuoteKind::Note => "note",
+            BlockQuoteKind::Tip => "tip",
+            BlockQuoteKind::Important => "important",
+            BlockQuoteKind::Warning => "warning",
+            BlockQuoteKind::Caution => "caution",
+        };
+        Callout { kind, title: capitalize(kind), fold: None }
+    }
+
+    /// Opening tag and title line; the end line is left as the `__BQ_END__` placeholder.
+    fn open_html(&self, start_line: usize) -> String {
+        let title = format!(
+            r#"<svg class="octicon" viewBox="0 0 16 16" width="16" height="16" aria-hidden="true"><path d="{}"></path></svg>{}"#,
+            callout_icon(self.kind), html_escape(&self.title)
+        );
+        let class = format!("markdown-alert markdown-alert-{}", self.kind);
+        match self.fold {
+            Some(open) => format!(
+                r#"<details class="{}"{} data-lines="{}-__BQ_END__"><summary class="markdown-alert-title">{}</summary>"#,
+                class, if open { " open" } else { "" }, start_line, title
+            ),
+            None => format!(
+                r#"<div class="{}" data-lines="{}-__BQ_END__"><p class="markdown-alert-title">{}</p>"#,
+                class, start_line, title
+            ),
+        }
+    }
+}
+
+/// Recognise an Obsidian callout marker (`[!type]`, optional `+`/`-`, optional title) on
+/// the first line of the blockquote at `start`. Also returns the marker's byte range.
+fn parse_callout(markdown: &str, start: usize) -> Option<(Callout, (usize, usize))> {
+    let line_end = markdown[start..].find('\n').map_or(markdown.len(), |i| start + i);
+    let line = markdown[start..line_end].trim_end_matches('\r');
+    let marker = line.trim_start().strip_prefix('>')?.trim_start();
+    let marker_start = start + line.len() - marker.len();
+
+    let rest = marker.strip_prefix("[!")?;
+    let close = rest.find(']')?;
+    let name = &rest[..close];
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let rest = &rest[close + 1..];
+    let (fold, rest) = match rest.strip_prefix('-') {
+        Some(rest) => (Some(false), rest),
+        None => match rest.strip_prefix('+') {
+            Some(rest) => (Some(true), rest),
+            None => (None, rest),
+        },
+    };
+    let title = match rest.trim() {
+        "" => capitalize(name),
+        title => title.to_string(),
+    };
+    Some((Callout { kind: callout_kind(name), title, fold }, (marker_start, line_end)))
+}
+
+/// Map Obsidian's callout types (and their aliases) onto the five GitHub alert styles.
+fn callout_kind(name: &str) -> &'static str {
+    known_callout_kind(name).unwrap_or("note")
+}
+
+/// The alert style for a callout type Obsidian or MkDocs defines, `None` for other words.
+fn known_callout_kind(name: &str) -> Option<&'static str> {
+    Some(match name.to_lowercase().as_str() {
+        "note" | "info" | "todo" | "quote" | "cite" => "note",
+        "tip" | "hint" | "success" | "check" | "done" => "tip",
+        "important" | "abstract" | "summary" | "tldr" | "example" => "important",
+        "warning" | "attention" | "question" | "help" | "faq" => "warning",
+        "caution" | "danger" | "error" | "failure" | "fail" | "missing" | "bug" => "caution",
+        _ => return None,
+    })
+}
+
+/// Octicon path for each alert style (info, light-bulb, report, alert, stop).
+fn callout_icon(kind: &str) -> &'static str {
+    match kind {
+        "tip" => "M8 1.5c-2.363 0-4 1.69-4 3.75 0 .984.424 1.625.984 2.304l.214.253c.223.264.47.556.673.848.284.411.537.896.621 1.49a.75.75 0 0 1-1.484.211c-.04-.282-.163-.547-.37-.847a8.456 8.456 0 0 0-.542-.68c-.084-.1-.173-.205-.268-.32C3.201 7.75 2.5 6.766 2.5 5.25 2.5 2.31 4.863 0 8 0s5.5 2.31 5.5 5.25c0 1.516-.701 2.5-1.328 3.259-.095.115-.184.22-.268.319-.207.245-.383.453-.541.681-.208.3-.33.565-.37.847a.751.751 0 0 1-1.485-.212c.084-.593.337-1.078.621-1.489.203-.292.45-.584.673-.848.075-.088.147-.173.213-.253.561-.679.985-1.32.985-2.304 0-2.06-1.637-3.75-4-3.75ZM5.75 12h4.5a.75.75 0 0 1 0 1.5h-4.5a.75.75 0 0 1 0-1.5ZM6 15.25a.75.75 0 0 1 .75-.75h2.5a.75.75 0 0 1 0 1.5h-2.5a.75.75 0 0 1-.75-.75Z",
+        "important" => "M0 1.75C0 .784.784 0 1.75 0h12.5C15.216 0 16 .784 16 1.75v9.5A1.75 1.75 0 0 1 14.25 13H8.06l-2.573 2.573A1.458 1.458 0 0 1 3 14.543V13H1.75A1.75 1.75 0 0 1 0 11.25Zm1.75-.25a.25.25 0 0 0-.25.25v9.5c0 .138.112.25.25.25h2a.75.75 0 0 1 .75.75v2.19l2.72-2.72a.749.749 0 0 1 .53-.22h6.5a.25.25 0 0 0 .25-.25v-9.5a.25.25 0 0 0-.25-.25Zm7 2.25v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 9a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z",
+        "warning" => "M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z",
+        "caution" => "M4.47.22A.749.749 0 0 1 5 0h6c.199 0 .389.079.53.22l4.25 4.25c.141.14.22.331.22.53v6a.749.749 0 0 1-.22.53l-4.25 4.25A.749.749 0 0 1 11 16H5a.749.749 0 0 1-.53-.22L.22 11.53A.749.749 0 0 1 0 11V5c0-.199.079-.389.22-.53Zm.84 1.28L1.5 5.31v5.38l3.81 3.81h5.38l3.81-3.81V5.31L10.69 1.5ZM8 4a.75.75 0 0 1 .75.75v3.5a.75.75 0 0 1-1.5 0v-3.5A.75.75 0 0 1 8 4Zm0 8a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z",
+        _ => "M0 8a8 8 0 1 1 16 0A8 8 0 0 1 0 8Zm8-6.5a6.5 6.5 0 1 0 0 13 6.5 6.5 0 0 0 0-13ZM6.5 7.75A.75.75 0 0 1 7.25 7h1a.75.75 0 0 1 .75.75v2.75h.25a.75.75 0 0 1 0 1.5h-2a.75.75 0 0 1 0-1.5h.25v-2h-.25a.75.75 0 0 1-.75-.75ZM8 6a1 1 0 1 1 0-2 1 1 0 0 1 0 2Z",
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars.flat_map(char::to_lowercase)).collect(),
+        None => String::new(),
+    }
+}
+
+/// Rewrite MkDocs admonitions (`!!! note "Title"`, or `???`/`???+` for collapsible ones,
+/// followed by an indented body) as `> [!note] Title` callouts. Every line stays on its
+/// original line number, so `data-lines` still point at the source.
+fn normalize_admonitions(markdown: &str) -> Cow<'_, str> {
+    if !markdown.contains("!!!") && !markdown.contains("???") {
+        return Cow::Borrowed(markdown);
+    }
+
+    let mut out = String::with_capacity(markdown.len() + 64);
+    let mut changed = false;
+    let mut fence: Option<&str> = None;
+    // Open admonition: its body (dedented) and trailing blank lines not yet known to belong to it
+    let mut admonition: Option<(String, String)> = None;
+
+    for line in markdown.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+
+        if let Some((body, blanks)) = admonition.as_mut() {
+            if content.trim().is_empty() {
+                blanks.push_str(line);
+                continue;
+            }
+            if let Some(rest) = content.strip_prefix("    ").or_else(|| content.strip_prefix('\t')) {
+                body.push_str(blanks);
+                blanks.clear();
+                body.push_str(&line[content.len() - rest.len()..]);
+                continue;
+            }
+            let (body, blanks) = admonition.take().unwrap_or_default();
+            push_quoted(&mut out, &body);
+            out.push_str(&blanks);
+        }
+
+        let trimmed = content.trim_start();
+        if let Some(marker) = fence {
+            if trimmed.starts_with(marker) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            let first = trimmed.as_bytes()[0];
+            let len = trimmed.bytes().take_while(|&b| b == first).count();
+            fence = Some(&trimmed[..len]);
+        } else if let Some(header) = admonition_header(content) {
+            out.push_str(&header);
+            out.push_str(&line[content.len()..]);
+            admonition = Some((String::new(), String::new()));
+            changed = true;
+            continue;
+        }
+        out.push_str(line);
+    }
+    if let Some((body, blanks)) = admonition {
+        push_quoted(&mut out, &body);
+        out.push_str(&blanks);
+    }
+
+    if changed { Cow::Owned(out) } else { Cow::Borrowed(markdown) }
+}
+
+/// `!!! type "Title"` -> `> [!type] Title` (`???` folds closed, `???+` folds open).
+fn admonition_header(line: &str) -> Option<String> {
+    let (fold, rest) = if let Some(rest) = line.strip_prefix("???+") {
+        ("+", rest)
+    } else if let Some(rest) = line.strip_prefix("???") {
+        ("-", rest)
+    } else {
+        ("", line.strip_prefix("!!!")?)
+    };
+    if !rest.starts_with([' ', '\t']) {
+        return None;
+    }
+    let rest = rest.trim();
+    let (kind, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    known_callout_kind(kind)?;
+    // Only MkDocs' placement classes (`!!! note inline end`) may come before the title
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix("inline").map_or(rest, |after| {
+        let after = after.trim_start();
+        after.strip_prefix("end").unwrap_or(after).trim_start()
+    });
+    let title = match rest {
+        "" => "",
+        _ => rest.strip_prefix('"')?.strip_suffix('"')?,
+    };
+    Some(format!("> [!{}]{} {}", kind, fold, title).trim_end().to_string())
+}
+
+/// Append `body` (normalized recursively, for nested admonitions) as blockquote lines.
+fn push_quoted(out: &mut String, body: &str) {
+    for line in normalize_admonitions(body).split_inclusive('\n') {
+        if line.trim().is_empty() {
+            out.push('>');
+            out.push_str(&line[line.trim_end_matches(['\n', '\r']).len()..]);
+        } else {
+            out.push_str("> ");
+            out.push_str(line);
+        }
+    }
+}
+
+/// What a fence's info string says: ```` ```rust title="main.rs" {3-5} linenums ````.
+/// Also understands MkDocs (`hl_lines="3 5"`, `linenums="10"`) and pandoc-style
+/// attributes (`{.python .numberLines startFrom="10"}`).
+#[derive(Debug, Default)]
+struct CodeInfo<'a> {
+    lang: Option<&'a str>,
+    /// Shown in a header above the code
+    title: Option<String>,
+    /// Lines to highlight, as 1-based inclusive ranges within the block
+    highlight: Vec<(usize, usize)>,
+    /// Number of the first line, if lines are numbered
+    first_line_number: Option<usize>,
+}
+
+impl<'a> CodeInfo<'a> {
+    fn apply(&mut self, token: &'a str, first: bool) {
+        if let Some(inner) = token.strip_prefix('{') {
+            let inner = inner.strip_suffix('}').unwrap_or(inner);
+            match parse_line_ranges(inner) {
+                Some(ranges) => self.highlight.extend(ranges),
+                None => info_tokens(inner).enumerate().for_each(|(i, token)| self.apply(token, first && i == 0)),
+            }
+            return;
+        }
+        if let Some(class) = token.strip_prefix('.') {
+            match class {
+                "numberLines" | "linenums" => { self.first_line_number.get_or_insert(1); }
+                _ => { self.lang.get_or_insert(class); }
+            }
+            return;
+        }
+        match token.split_once('=') {
+            Some((key, value)) => {
+                let value = unquote(value);
+                match key {
+                    "title" | "filename" | "file" => self.title = Some(value.to_string()),
+                    "hl_lines" | "highlight" => self.highlight.extend(parse_line_ranges(value).unwrap_or_default()),
+                    "linenums" | "linenostart" | "startFrom" | "startline" => {
+                        self.first_line_number = Some(value.parse().unwrap_or(1));
+                    }
+                    _ => {}
+                }
+            }
+            None => match token {
+                "linenums" | "showLineNumbers" | "numberLines" => { self.first_line_number.get_or_insert(1); }
+                _ if first => self.lang = Some(token),
+                _ => {}
+            },
+        }
+    }
+
+    /// `data-highlight` / `data-linenums` for the `<pre>`, read by the page script.
+    fn pre_attributes(&self) -> String {
+        let mut attributes = String::new();
+        if !self.highlight.is_empty() {
+            let ranges: Vec<String> = self.highlight.iter().map(|(start, end)| format!("{}-{}", start, end)).collect();
+            attributes.push_str(&format!(r#" data-highlight="{}""#, ranges.join(",")));
+        }
+        if let Some(first) = self.first_line_number {
+            attributes.push_str(&format!(r#" data-linenums="{}""#, first));
+        }
+        attributes
+    }
+}
+
+fn parse_code_info(info: &str) -> CodeInfo<'_> {
+    let mut code_info = CodeInfo::default();
+    for (i, token) in info_tokens(info).enumerate() {
+        code_info.apply(token, i == 0);
+    }
+    code_info
+}
+
+/// Language named by a fence's info string (`rust` in ```` ```rust title="main.rs" ````).
+pub(crate) fn code_language(info: &str) -> Option<&str> {
+    parse_code_info(info).lang
+}
+
+/// Split an info string into words, `key="quoted value"` pairs and `{...}` groups.
+fn info_tokens(info: &str) -> impl Iterator<Item = &str> {
+    let mut rest = info.trim();
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let len = if rest.starts_with('{') {
+            rest.find('}').map_or(rest.len(), |i| i + 1)
+        } else {
+            let mut quote = None;
+            rest.char_indices()
+                .find(|&(i, c)| match quote {
+                    Some(q) => {
+                        if c == q {
+                            quote = None;
+                        }
+                        false
+                    }
+                    None if c == '"' || c == '\'' => {
+                        quote = Some(c);
+                        false
+                    }
+                    None => c.is_whitespace() || (c == '{' && i > 0),
+                })
+                .map_or(rest.len(), |(i, _)| i)
+        };
+        let token = &rest[..len];
+        rest = rest[len..].trim_start();
+        Some(token)
+    })
+}
+
+/// `3-5`, `1,3-5` or `3 5`: 1-based inclusive ranges, or `None` if `text` is anything else.
+fn parse_line_ranges(text: &str) -> Option<Vec<(usize, usize)>> {
+    let ranges: Option<Vec<(usize, usize)>> = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => Some((start.trim().parse().ok()?, end.trim().parse().ok()?)),
+            None => part.parse().ok().map(|line| (line, line)),
+        })
+        .collect();
+    ranges.filter(|ranges| !ranges.is_empty())
+}
+
+/// Opening of the placeholder shown while a block's command runs in the background.
+const PENDING_BLOCK: &str = r#"<div class="rendered-block render-pending""#;
+
+/// Whether rendered `html` still has blocks waiting for their command.
+pub(crate) fn has_pending_blocks(html: &str) -> bool {
+    html.contains(PENDING_BLOCK)
+}
+
+/// A fenced block rendered by an external command: the image, or an error box with the
+/// block's source (also shown while the command runs in the background).
+fn external_block_html(command: &str, source: &str, start_line: usize, end_line: usize) -> String {
+    match render_with_command(command, source) {
+        None => format!(
+            r#"{} data-lines="{}-{}"><div class="render-pending-title">Rendering with `{}`…</div><pre><code>{}</code></pre></div>"#,
+            PENDING_BLOCK, start_line, end_line, html_escape(command), html_escape(source)
+        ) + "\n",
+        Some(Ok(data_uri)) => format!(
+            r#"<div class="rendered-block" data-lines="{}-{}"><img src="{}" alt="" /></div>"#,
+            start_line, end_line, data_uri
+        ) + "\n",
+        Some(Err(message)) => format!(
+            r#"<div class="rendered-block render-error" data-lines="{}-{}"><div class="render-error-title">{}</div><pre><code>{}</code></pre></div>"#,
+            start_line, end_line, html_escape(&message), html_escape(source)
+        ) + "\n",
+    }
+}
+
+fn flush_pending_tag(output: &mut String, tag: &Option<String>, start_line: Option<usize>, end_line: usize) {
+    if let (Some(tag), Some(start)) = (tag, start_line) {
+        output.push_str(&format!(r#"<{} data-lines="{}-{}">"#, tag, start, end_line));
+    }
+}
+
+/// GitHub's anchor for a heading: lowercased, punctuation dropped, each space turned
+/// into `-` (so `"Foo & Bar"` becomes `"foo--bar"`). See [`Slugger`] for duplicates.
+pub fn slugify(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            _ if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Hands out unique heading anchors the way GitHub does: the second "Usage" heading
+/// becomes `usage-1`, the third `usage-2`, and so on.
+#[derive(Debug, Default)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = slugify(text);
+        let mut slug = base.clone();
+        while self.occurrences.contains_key(&slug) {
+            let count = self.occurrences.entry(base.clone()).or_insert(0);
+            *count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+        self.occurrences.insert(slug.clone(), 0);
+        slug
+    }
+
+    /// The anchor for a heading: its explicit `{#id}` if it has one (kept as written, and
+    /// reserved so later headings don't get the same slug), otherwise [`slug`](Self::slug).
+    pub fn heading_id(&mut self, text: &str, id: Option<&str>) -> String {
+        match id {
+            Some(id) => {
+                self.occurrences.entry(id.to_string()).or_insert(0);
+                id.to_string()
+            }
+            None => self.slug(text),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugify_keeps_letters_digits_dashes_and_underscores() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("C++ & Rust"), "c--rust");
+        assert_eq!(slugify("snake_case and-dash"), "snake_case-and-dash");
+        assert_eq!(slugify("Ünïcode Tëxt"), "ünïcode-tëxt");
+    }
+
+    #[test]
+    fn repeated_headings_get_numbered_suffixes() {
+        let mut slugger = Slugger::default();
+        let slugs: Vec<String> = ["Usage", "Usage", "Usage"].iter().map(|text| slugger.slug(text)).collect();
+        assert_eq!(slugs, ["usage", "usage-1", "usage-2"]);
+    }
+
+    #[test]
+    fn suffixes_skip_slugs_taken_by_other_headings() {
+        let mut slugger = Slugger::default();
+        let slugs: Vec<String> = ["Usage", "Usage-1", "Usage"].iter().map(|text| slugger.slug(text)).collect();
+        assert_eq!(slugs, ["usage", "usage-1", "usage-2"]);
+
+        let mut slugger = Slugger::default();
+        let slugs: Vec<String> = ["Usage-1", "Usage", "Usage"].iter().map(|text| slugger.slug(text)).collect();
+        assert_eq!(slugs, ["usage-1", "usage", "usage-2"]);
+    }
+
+    #[test]
+    fn explicit_ids_are_kept_and_reserved() {
+        let mut slugger = Slugger::default();
+        assert_eq!(slugger.heading_id("Setup", Some("My_Setup")), "My_Setup");
+        assert_eq!(slugger.heading_id("Install", Some("setup")), "setup");
+        assert_eq!(slugger.heading_id("Setup", None), "setup-1");
+    }
+
+    #[test]
+    fn rendered_ids_match_the_outline() {
+        let markdown = "# Intro {#start}\n\n# Start\n\n## Intro\n\n## Intro\n";
+        let html = markdown_to_html(markdown, None);
+        let toc = extract_toc(markdown);
+        let slugs: Vec<&str> = toc.iter().map(|entry| entry.slug.as_str()).collect();
+        assert_eq!(slugs, ["start", "start-1", "intro", "intro-1"]);
+        for slug in slugs {
+            assert!(html.contains(&format!(r#"id="{}""#, slug)), "{} missing from {}", slug, html);
+        }
+    }
+
+    #[test]
+    fn dollar_amounts_stay_text() {
+        let html = markdown_to_html("It costs $5 and $6 today.\n", None);
+        assert!(!html.contains("math"), "{}", html);
+        assert!(html.contains("$5 and $6"), "{}", html);
+    }
+
+    #[test]
+    fn latex_delimiters_become_dollar_math() {
+        assert_eq!(normalize_math_delimiters("where \\(x^2\\) holds\n"), "where $x^2$ holds\n");
+        assert_eq!(normalize_math_delimiters("\\[\na + b\n\\]\n"), "$$\na + b\n$$\n");
+        assert_eq!(normalize_math_delimiters("\\[ e = mc^2 \\]\n"), "$$ e = mc^2 $$\n");
+
+        let html = markdown_to_html("Before\n\n\\[\n\\sum_i x_i\n\\]\n\nAfter\n", None);
+        assert!(html.contains("math-display"), "{}", html);
+    }
+
+    #[test]
+    fn escaped_parens_and_brackets_in_prose_stay_prose() {
+        for text in ["Call foo\\(bar\\) here\n", "See note \\[1\\] below\n", "An open \\( alone\n", "`\\(x\\)` in code\n"] {
+            assert_eq!(normalize_math_delimiters(text), text);
+        }
+        assert_eq!(normalize_math_delimiters("```\n\\[x\\]\n```\n"), "```\n\\[x\\]\n```\n");
+        assert!(!markdown_to_html("Call foo\\(bar\\) here\n", None).contains("math"));
+    }
+
+    #[test]
+    fn admonition_headers() {
+        assert_eq!(admonition_header(r#"!!! note "Read this""#).as_deref(), Some("> [!note] Read this"));
+        assert_eq!(admonition_header("!!! warning").as_deref(), Some("> [!warning]"));
+        assert_eq!(admonition_header("???+ tip").as_deref(), Some("> [!tip]+"));
+        assert_eq!(admonition_header(r#"??? info "Details""#).as_deref(), Some("> [!info]- Details"));
+        assert_eq!(admonition_header(r#"!!! note inline end "Aside""#).as_deref(), Some("> [!note] Aside"));
+        assert_eq!(admonition_header("!!! foo"), None);
+        assert_eq!(admonition_header("!!!note"), None);
+        assert_eq!(admonition_header("!!! note Title without quotes"), None);
+    }
+
+    #[test]
+    fn admonitions_become_callouts_on_the_same_lines() {
+        let markdown = "!!! note \"Title\"\n    First line.\n\n    Second line.\n\nAfter.\n";
+        assert_eq!(normalize_admonitions(markdown), "> [!note] Title\n> First line.\n>\n> Second line.\n\nAfter.\n");
+
+        let html = markdown_to_html(markdown, None);
+        assert!(html.contains("markdown-alert-note"), "{}", html);
+        assert!(html.contains("Title"), "{}", html);
+
+        let html = markdown_to_html("???+ tip\n    Open by default.\n", None);
+        assert!(html.contains(r#"<details class="markdown-alert markdown-alert-tip" open"#), "{}", html);
+    }
+
+    #[test]
+    fn unknown_admonition_types_stay_prose() {
+        let markdown = "!!! foo\n    Not a callout.\n";
+        assert!(matches!(normalize_admonitions(markdown), Cow::Borrowed(_)));
+        assert!(!markdown_to_html(markdown, None).contains("markdown-alert"));
+        let fenced = "```\n!!! note\n    code\n```\n";
+        assert!(matches!(normalize_admonitions(fenced), Cow::Borrowed(_)));
+    }
+
+    fn autolinks<'a>(text: &'a str, config: &DirConfig) -> Vec<(&'a str, String)> {
+        find_autolinks(text, config).into_iter().map(|(range, href)| (&text[range], href)).collect()
+    }
+
+    #[test]
+    fn autolinks_in_text() {
+        let config = DirConfig { repo_url: Some("https://github.com/acme/widgets/".to_string()), ..DirConfig::default() };
+        let cases: &[(&str, &[(&str, &str)])] = &[
+            // Trailing punctuation belongs to the sentence
+            ("Go to https://example.com, then", &[("https://example.com", "https://example.com")]),
+            ("Is it https://example.com/a?", &[("https://example.com/a", "https://example.com/a")]),
+            ("See www.example.org.", &[("www.example.org", "http://www.example.org")]),
+            ("Said https://example.com/x'", &[("https://example.com/x", "https://example.com/x")]),
+            ("Fish &amp; https://example.com/a&amp;", &[("https://example.com/a", "https://example.com/a")]),
+            // Parentheses are kept only when balanced
+            ("(see https://example.com)", &[("https://example.com", "https://example.com")]),
+            ("https://en.wikipedia.org/wiki/Foo_(bar)", &[("https://en.wikipedia.org/wiki/Foo_(bar)", "https://en.wikipedia.org/wiki/Foo_(bar)")]),
+            ("(https://en.wikipedia.org/wiki/Foo_(bar))", &[("https://en.wikipedia.org/wiki/Foo_(bar)", "https://en.wikipedia.org/wiki/Foo_(bar)")]),
+            // Emails, but not the user part of a URL
+            ("Mail jane.doe+list@example.co.uk.", &[("jane.doe+list@example.co.uk", "mailto:jane.doe+list@example.co.uk")]),
+            ("https://user@example.com/x", &[("https://user@example.com/x", "https://user@example.com/x")]),
+            ("user@localhost", &[]),
+            // Issue references, against the configured repository
+            ("Fixed in #12.", &[("#12", "https://github.com/acme/widgets/issues/12")]),
+            ("See rust-lang/rust#123", &[("rust-lang/rust#123", "https://github.com/rust-lang/rust/issues/123")]),
+            ("a#12 and #12b and #", &[]),
+            ("(#7)", &[("#7", "https://github.com/acme/widgets/issues/7")]),
+        ];
+        for (text, expected) in cases {
+            let expected: Vec<(&str, String)> = expected.iter().map(|&(link, href)| (link, href.to_string())).collect();
+            assert_eq!(autolinks(text, &config), expected, "in {:?}", text);
+        }
+    }
+
+    #[test]
+    fn issue_refs_need_a_configured_repository() {
+        assert_eq!(autolinks("Fixed in #12 and owner/repo#3", &DirConfig::default()), []);
+        assert_eq!(match_issue_ref("#12", &DirConfig::default()), None);
+    }
+
+    #[test]
+    fn url_and_issue_matchers_stop_at_the_link_end() {
+        let config = DirConfig { repo_url: Some("https://github.com/acme/widgets".to_string()), ..DirConfig::default() };
+        assert_eq!(match_url("https://example.com/path). More"), Some((24, "https://example.com/path".to_string())));
+        assert_eq!(match_url("www.example.com:8080/x!"), Some((22, "http://www.example.com:8080/x".to_string())));
+        assert_eq!(match_url("https://"), None);
+        assert_eq!(match_url("example.com"), None);
+        assert_eq!(match_issue_ref("#42, thanks", &config), Some((3, "https://github.com/acme/widgets/issues/42".to_string())));
+        assert_eq!(match_issue_ref("owner/repo#123)", &config), Some((14, "https://github.com/owner/repo/issues/123".to_string())));
+        assert_eq!(match_issue_ref("owner/.repo#1", &config), None);
+        assert_eq!(match_issue_ref("#x", &config), None);
+    }
+}
@@ -0,0 +1,638 @@
+// ============================================================================
+// NOTEBOOK TYPES
+// ============================================================================
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::config::DirConfig;
+use crate::ansi::{ansi_to_html, strip_ansi_codes};
+use crate::highlight::highlight_code_html;
+use crate::html_escape;
+use crate::markdown::{extract_headings_from_markdown, markdown_to_html_with_slugger, Slugger};
+use crate::TocEntry;
+
+/// A Jupyter notebook (`.ipynb`), deserialized with `serde_json`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Notebook {
+    pub cells: Vec<NotebookCell>,
+    pub metadata: Option<Value>,
+}
+
+impl Notebook {
+    /// Kernel language from the notebook metadata (`language_info.name`, then
+    /// `kernelspec.language`), defaulting to `"python"`.
+    pub fn language(&self) -> &str {
+        let metadata = self.metadata.as_ref();
+        metadata
+            .and_then(|m| m.pointer("/language_info/name"))
+            .or_else(|| metadata.and_then(|m| m.pointer("/kernelspec/language")))
+            .and_then(Value::as_str)
+            .filter(|lang| !lang.is_empty())
+            .unwrap_or("python")
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NotebookCell {
+    /// `"markdown"`, `"code"` or `"raw"`
+    pub cell_type: String,
+    pub source: StringOrArray,
+    #[serde(default)]
+    pub outputs: Vec<CellOutput>,
+    pub execution_count: Option<i64>,
+}
+
+/// Multiline notebook strings may be stored as one string or a list of lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum StringOrArray {
+    String(String),
+    Array(Vec<String>),
+}
+
+impl fmt::Display for StringOrArray {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StringOrArray::String(s) => f.write_str(s),
+            StringOrArray::Array(arr) => arr.iter().try_for_each(|line| f.write_str(line)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CellOutput {
+    /// `"stream"`, `"execute_result"`, `"display_data"` or `"error"`
+    pub output_type: String,
+    #[serde(default)]
+    pub name: Option<String>, // "stdout" or "stderr" for stream outputs
+    #[serde(default)]
+    pub text: Option<StringOrArray>,
+    #[serde(default)]
+    pub data: Option<HashMap<String, StringOrArray>>,
+    #[serde(default)]
+    pub ename: Option<String>,
+    #[serde(default)]
+    pub evalue: Option<String>,
+    #[serde(default)]
+    pub traceback: Option<Vec<String>>,
+}
+
+/// Storage for truncated output lines (for "show more" functionality)
+#[derive(Debug, Clone)]
+pub struct TruncatedOutput {
+    pub full_lines: Vec<String>,  // All lines, pre-escaped HTML
+    pub total_lines: usize,
+    pub shown_lines: usize,       // How many currently shown (200 initially)
+}
+
+/// Truncated outputs keyed by `(cell_idx, output_idx)`
+pub type TruncatedOutputs = HashMap<(usize, usize), TruncatedOutput>;
+
+// ============================================================================
+// NOTEBOOK RENDERING
+// ============================================================================
+
+/// An image extracted from a notebook output by [`export_notebook_markdown`].
+#[derive(Debug, Clone)]
+pub struct ExportedImage {
+    /// Path relative to the markdown file, as linked from it (e.g. `analysis_files/output_3_0.png`)
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Where image outputs go when flattening a notebook to markdown.
+enum ImageTarget<'a> {
+    /// Inline as data URIs (in-app terminal view and smart copy)
+    Inline,
+    /// Collected as files under `dir`, linked by relative path
+    Files { dir: &'a str, images: &'a mut Vec<ExportedImage> },
+}
+
+/// Flatten a notebook into a single markdown document (code and outputs as fences).
+/// Images are inlined as data URIs.
+pub fn notebook_to_markdown(notebook: &Notebook) -> String {
+    write_notebook_markdown(notebook, ImageTarget::Inline)
+}
+
+/// Flatten a notebook into markdown for committing next to it: images are returned as
+/// files under `images_dir` instead of data URIs, and HTML outputs (e.g. DataFrames) are
+/// kept as HTML so they render on GitHub.
+pub fn export_notebook_markdown(notebook: &Notebook, images_dir: &str) -> (String, Vec<ExportedImage>) {
+    let mut images = Vec::new();
+    let md = write_notebook_markdown(notebook, ImageTarget::Files { dir: images_dir, images: &mut images });
+    (md, images)
+}
+
+fn write_notebook_markdown(notebook: &Notebook, mut target: ImageTarget) -> String {
+    let language = notebook.language();
+    let mut md = String::new();
+
+    for (i, cell) in notebook.cells.iter().enumerate() {
+        if i > 0 {
+            md.push_str("\n---\n\n");
+        }
+
+        match cell.cell_type.as_str() {
+            "markdown" => {
+                md.push_str(&cell.source.to_string());
+                md.push_str("\n\n");
+            }
+            "code" => {
+                push_fenced(&mut md, language, &cell.source.to_string());
+
+                for (j, output) in cell.outputs.iter().enumerate() {
+                    match output.output_type.as_str() {
+                        "stream" => {
+                            if let Some(text) = &output.text {
+                                push_fenced(&mut md, "", &text.to_string());
+                            }
+                        }
+                        "execute_result" | "display_data" => {
+                            if let Some(data) = &output.data {
+                                push_data_output(&mut md, data, &mut target, (i, j));
+                            }
+                        }
+                        "error" => {
+                            let mut text = String::new();
+                            if let Some(ename) = &output.ename {
+                                text.push_str(ename);
+                                if let Some(evalue) = &output.evalue {
+                                    text.push_str(": ");
+                                    text.push_str(evalue);
+                                }
+                                text.push('\n');
+                            }
+                            if let Some(tb) = &output.traceback {
+                                for line in tb {
+                                    // Strip ANSI codes from traceback
+                                    text.push_str(&strip_ansi_codes(line));
+                                    text.push('\n');
+                                }
+                            }
+                            push_fenced(&mut md, "", &text);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "raw" => push_fenced(&mut md, "", &cell.source.to_string()),
+            _ => {}
+        }
+    }
+
+    md
+}
+
+/// Append a rich output, preferring images, then LaTeX, then (when exporting) HTML, then plain text.
+fn push_data_output(md: &mut String, data: &HashMap<String, StringOrArray>, target: &mut ImageTarget, (cell_idx, output_idx): (usize, usize)) {
+    use base64::Engine;
+    let b64 = base64::engine::general_purpose::STANDARD;
+
+    let image = [("image/png", "png"), ("image/jpeg", "jpg"), ("image/svg+xml", "svg")]
+        .into_iter()
+        .find_map(|(mime, ext)| data.get(mime).map(|value| (mime, ext, value.to_string())));
+    if let Some((mime, ext, value)) = image {
+        // SVG is stored as text, bitmaps as base64
+        let is_svg = mime == "image/svg+xml";
+        let src = match target {
+            ImageTarget::Inline if is_svg => format!("data:{};base64,{}", mime, b64.encode(&value)),
+            ImageTarget::Inline => format!("data:{};base64,{}", mime, value.replace('\n', "")),
+            ImageTarget::Files { dir, images } => {
+                let bytes = if is_svg {
+                    value.into_bytes()
+                } else {
+                    match b64.decode(value.replace('\n', "")) {
+                        Ok(bytes) => bytes,
+                        Err(_) => return,
+                    }
+                };
+                let path = format!("{}/output_{}_{}.{}", dir, cell_idx, output_idx, ext);
+                images.push(ExportedImage { path: path.clone(), bytes });
+                if path.contains(' ') {
+                    format!("<{}>", path)
+                } else {
+                    path
+                }
+            }
+        };
+        md.push_str(&format!("![output]({})\n\n", src));
+    } else if let Some(latex) = data.get("text/latex") {
+        md.push_str(latex.to_string().trim());
+        md.push_str("\n\n");
+    } else if let Some(html) = data.get("text/html").filter(|_| matches!(target, ImageTarget::Files { .. })) {
+        // Blank lines would end the HTML block early, so drop them
+        let html = html.to_string();
+        let lines: Vec<&str> = html.lines().filter(|line| !line.trim().is_empty()).collect();
+        md.push_str(&lines.join("\n"));
+        md.push_str("\n\n");
+    } else if let Some(text) = data.get("text/plain") {
+        push_fenced(md, "", &text.to_string());
+    }
+}
+
+/// Append `text` as a fenced code block, with a fence longer than any backtick run inside it.
+fn push_fenced(md: &mut String, lang: &str, text: &str) {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    md.push_str(&fence);
+    md.push_str(lang);
+    md.push('\n');
+    md.push_str(text);
+    if !text.ends_with('\n') {
+        md.push('\n');
+    }
+    md.push_str(&fence);
+    md.push_str("\n\n");
+}
+
+// Strip outer <pre style="..."> wrapper from HTML but keep inner content
+fn strip_pre_wrapper(html: &str) -> String {
+    let trimmed = html.trim();
+    // Check if it starts with <pre and ends with </pre>
+    if trimmed.starts_with("<pre") && trimmed.ends_with("</pre>") {
+        // Find the end of opening tag
+        if let Some(end_tag_pos) = trimmed.find('>') {
+            let inner = &trimmed[end_tag_pos + 1..trimmed.len() - 6]; // remove "</pre>"
+            // Also strip trailing newline in inner content
+            return inner.trim_end_matches('\n').to_string();
+        }
+    }
+    html.to_string()
+}
+
+/// Headings of all markdown cells, tagged with their cell index.
+pub fn extract_notebook_toc(notebook: &Notebook) -> Vec<TocEntry> {
+    let mut toc = Vec::new();
+    let mut slugger = Slugger::default();
+    for (cell_idx, cell) in notebook.cells.iter().enumerate() {
+        if cell.cell_type == "markdown" {
+            let first_heading = toc.len();
+            extract_headings_from_markdown(&cell.source.to_string(), &mut toc, &mut slugger);
+            for entry in &mut toc[first_heading..] {
+                entry.cell = Some(cell_idx);
+            }
+        }
+    }
+    toc
+}
+
+/// Convert notebook to native HTML rendering.
+/// With `truncate_outputs`, long outputs show head/tail only and the rest is fetched on demand.
+pub fn notebook_to_html(notebook: &Notebook, base_dir: Option<&Path>, truncate_outputs: bool) -> (String, Vec<TocEntry>, TruncatedOutputs) {
+    let mut html = String::from("<div class=\"notebook\">\n");
+    let toc = extract_notebook_toc(notebook);
+    let mut truncated_outputs = TruncatedOutputs::new();
+    // Heading ids are unique across cells, matching the TOC
+    let mut slugger = Slugger::default();
+    let config = DirConfig::load(base_dir);
+    let language = notebook.language();
+
+    for (cell_idx, cell) in notebook.cells.iter().enumerate() {
+        match cell.cell_type.as_str() {
+            "markdown" => {
+                let md_source = cell.source.to_string();
+                // Render markdown using existing function
+                let rendered = markdown_to_html_with_slugger(&md_source, base_dir, &config, &mut slugger);
+                html.push_str(&format!(
+                    "<div class=\"nb-cell nb-markdown-cell\" data-cell-idx=\"{}\">\n{}\n</div>\n",
+                    cell_idx, rendered
+                ));
+            }
+            "code" => {
+                let exec_count = cell.execution_count.map(|n| n.to_string()).unwrap_or_else(|| " ".to_string());
+                let source = cell.source.to_string();
+                let (code_class, code_html) = match highlight_code_html(&source, language) {
+                    Some(highlighted) => (format!("language-{} hljs", html_escape(language)), highlighted),
+                    None => (format!("language-{}", html_escape(language)), html_escape(&source)),
+                };
+
+                html.push_str(&format!(
+                    r#"<div class="nb-cell nb-code-cell" data-cell-idx="{}">
+    <div class="nb-cell-header">
+        <span class="nb-prompt nb-in">In [{}]:</span>
+        <button class="nb-collapse-btn">▼</button>
+    </div>
+    <div class="nb-input">
+        <pre><code class="{}">{}</code></pre>
+    </div>
+"#,
+                    cell_idx, exec_count, code_class, code_html
+                ));
+
+                // Render outputs (merging consecutive stream outputs)
+                if !cell.outputs.is_empty() {
+                    html.push_str("    <div class=\"nb-outputs\">\n");
+                    let mut output_idx = 0;
+                    let mut i = 0;
+                    while i < cell.outputs.len() {
+                        let output = &cell.outputs[i];
+
+                        if output.output_type == "stream" {
+                            // Collect consecutive stream outputs
+                            let mut merged_lines: Vec<String> = Vec::new();
+                            while i < cell.outputs.len() && cell.outputs[i].output_type == "stream" {
+                                let stream_output = &cell.outputs[i];
+                                if let Some(text) = &stream_output.text {
+                                    let is_stderr = stream_output.name.as_deref() == Some("stderr");
+                                    for line in text.to_string().lines() {
+                                        let escaped = html_escape(line);
+                                        if is_stderr {
+                                            // Check if it's a warning line
+                                            let lower = line.to_lowercase();
+                                            if lower.contains("warning") {
+                                                merged_lines.push(format!("<span class=\"stderr-warning\">{}</span>", escaped));
+                                            } else {
+                                                merged_lines.push(format!("<span class=\"stderr\">{}</span>", escaped));
+                                            }
+                                        } else {
+                                            merged_lines.push(escaped);
+                                        }
+                                    }
+                                }
+                                i += 1;
+                            }
+
+                            // Render merged stream output
+                            if !merged_lines.is_empty() {
+                                if let Some(truncated) = render_merged_stream(&mut html, &merged_lines, cell_idx, output_idx, truncate_outputs) {
+                                    truncated_outputs.insert((cell_idx, output_idx), truncated);
+                                }
+                                output_idx += 1;
+                            }
+                        } else {
+                            // Non-stream output: render normally
+                            if let Some(truncated) = render_output(&mut html, output, &exec_count, cell_idx, output_idx, truncate_outputs) {
+                                truncated_outputs.insert((cell_idx, output_idx), truncated);
+                            }
+                            output_idx += 1;
+                            i += 1;
+                        }
+                    }
+                    html.push_str("    </div>\n");
+                }
+
+                html.push_str("</div>\n");
+            }
+            "raw" => {
+                let source = html_escape(&cell.source.to_string());
+                html.push_str(&format!(
+                    r#"<div class="nb-cell nb-raw-cell" data-cell-idx="{}">
+    <div class="nb-raw-content">{}</div>
+</div>
+"#,
+                    cell_idx, source
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    html.push_str("</div>\n");
+
+    (html, toc, truncated_outputs)
+}
+
+// Helper to render truncated text output with "show more" UI
+// Shows first 200 lines + last 10 lines, only if hidden > 80
+fn render_truncated_text(
+    html: &mut String,
+    lines: &[String],
+    cell_idx: usize,
+    output_idx: usize,
+    css_class: &str,
+    prompt_html: &str,
+) -> TruncatedOutput {
+    let total = lines.len();
+    let head_lines = &lines[..200];
+    let tail_lines = &lines[total - 10..];
+    let hidden = total - 210;
+
+    html.push_str(&format!(
+        r#"        <div class="{}" data-cell-idx="{}" data-output-idx="{}">
+            {}
+            <div class="nb-output-content"><div class="nb-output-head">{}</div>
+            <div class="nb-output-truncated">
+                <span class="nb-truncated-info">{} lines hidden</span>
+                <button class="nb-show-more" data-amount="50">Show 50 more</button>
+                <button class="nb-show-all">Show all</button>
+            </div>
+            <div class="nb-output-tail">{}</div></div>
+        </div>
+"#,
+        css_class,
+        cell_idx,
+        output_idx,
+        prompt_html,
+        head_lines.join("\n"),
+        hidden,
+        tail_lines.join("\n")
+    ));
+
+    TruncatedOutput {
+        full_lines: lines.to_vec(),
+        total_lines: total,
+        shown_lines: 200,
+    }
+}
+
+// Render merged stream outputs (consecutive stdout/stderr combined into one block)
+fn render_merged_stream(
+    html: &mut String,
+    lines: &[String],
+    cell_idx: usize,
+    output_idx: usize,
+    truncate: bool,
+) -> Option<TruncatedOutput> {
+    if lines.is_empty() {
+        return None;
+    }
+
+    if truncate && lines.len() > 290 {
+        Some(render_truncated_text(
+            html,
+            lines,
+            cell_idx,
+            output_idx,
+            "nb-output nb-output-stream",
+            "",
+        ))
+    } else {
+        html.push_str(&format!(
+            r#"        <div class="nb-output nb-output-stream">
+            <div class="nb-output-content">{}</div>
+        </div>
+"#,
+            lines.join("\n")
+        ));
+        None
+    }
+}
+
+fn render_output(
+    html: &mut String,
+    output: &CellOutput,
+    exec_count: &str,
+    cell_idx: usize,
+    output_idx: usize,
+    truncate: bool,
+) -> Option<TruncatedOutput> {
+    match output.output_type.as_str() {
+        "stream" => {
+            if let Some(text) = &output.text {
+                let text_str = text.to_string();
+                let lines: Vec<String> = text_str.lines().map(html_escape).collect();
+
+                if truncate && lines.len() > 290 {
+                    return Some(render_truncated_text(
+                        html,
+                        &lines,
+                        cell_idx,
+                        output_idx,
+                        "nb-output nb-output-stream",
+                        "",
+                    ));
+                } else {
+                    let escaped = html_escape(&text_str);
+                    html.push_str(&format!(
+                        r#"        <div class="nb-output nb-output-stream">
+            <div class="nb-output-content">{}</div>
+        </div>
+"#,
+                        escaped
+                    ));
+                }
+            }
+        }
+        "execute_result" | "display_data" => {
+            if let Some(data) = &output.data {
+                // Check for images first (prioritize visual output)
+                if let Some(img) = data.get("image/png") {
+                    let b64 = img.to_string().replace('\n', "");
+                    html.push_str(&format!(
+                        r#"        <div class="nb-output nb-output-image">
+            <img src="data:image/png;base64,{}" class="nb-figure" alt="output">
+        </div>
+"#,
+                        b64
+                    ));
+                } else if let Some(img) = data.get("image/jpeg") {
+                    let b64 = img.to_string().replace('\n', "");
+                    html.push_str(&format!(
+                        r#"        <div class="nb-output nb-output-image">
+            <img src="data:image/jpeg;base64,{}" class="nb-figure" alt="output">
+        </div>
+"#,
+                        b64
+                    ));
+                } else if let Some(svg) = data.get("image/svg+xml") {
+                    // SVG can be embedded directly as HTML
+                    let svg_str = svg.to_string();
+                    html.push_str(&format!(
+                        r#"        <div class="nb-output nb-output-svg">
+            {}
+        </div>
+"#,
+                        svg_str
+                    ));
+                } else if let Some(html_content) = data.get("text/html") {
+                    // HTML output - no truncation per design decision
+                    let html_str = html_content.to_string();
+                    let cleaned = strip_pre_wrapper(&html_str);
+                    let prompt = if output.output_type == "execute_result" {
+                        format!(r#"<div class="nb-output-header"><span class="nb-prompt nb-out">Out[{}]:</span></div>"#, exec_count)
+                    } else {
+                        String::new()
+                    };
+                    html.push_str(&format!(
+                        r#"        <div class="nb-output nb-output-html">
+            {}
+            <div class="nb-output-content">{}</div>
+        </div>
+"#,
+                        prompt, cleaned
+                    ));
+                } else if let Some(text) = data.get("text/plain") {
+                    let text_str = text.to_string();
+                    let lines: Vec<String> = text_str.lines().map(html_escape).collect();
+
+                    let prompt = if output.output_type == "execute_result" {
+                        format!(r#"<div class="nb-output-header"><span class="nb-prompt nb-out">Out[{}]:</span></div>"#, exec_count)
+                    } else {
+                        String::new()
+                    };
+
+                    if truncate && lines.len() > 290 {
+                        return Some(render_truncated_text(
+                            html,
+                            &lines,
+                            cell_idx,
+                            output_idx,
+                            "nb-output nb-output-text",
+                            &prompt,
+                        ));
+                    } else {
+                        let escaped = html_escape(&text_str);
+                        html.push_str(&format!(
+                            r#"        <div class="nb-output nb-output-text">
+            {}
+            <div class="nb-output-content">{}</div>
+        </div>
+"#,
+                            prompt, escaped
+                        ));
+                    }
+                }
+            }
+        }
+        "error" => {
+            // Build error lines for potential truncation
+            let mut error_lines: Vec<String> = Vec::new();
+
+            if let Some(ename) = &output.ename {
+                let mut first_line = format!("<span style=\"color:#e06c75;font-weight:bold\">{}</span>", html_escape(ename));
+                if let Some(evalue) = &output.evalue {
+                    first_line.push_str(": ");
+                    first_line.push_str(&html_escape(evalue));
+                }
+                error_lines.push(first_line);
+            }
+
+            if let Some(tb) = &output.traceback {
+                for line in tb {
+                    let colored = ansi_to_html(line);
+                    error_lines.push(colored);
+                }
+            }
+
+            if truncate && error_lines.len() > 290 {
+                return Some(render_truncated_text(
+                    html,
+                    &error_lines,
+                    cell_idx,
+                    output_idx,
+                    "nb-output nb-output-error",
+                    "",
+                ));
+            } else {
+                let error_html = error_lines.join("\n");
+                html.push_str(&format!(
+                    r#"        <div class="nb-output nb-output-error">
+            <div class="nb-output-content">{}</div>
+        </div>
+"#,
+                    error_html
+                ));
+            }
+        }
+        _ => {}
+    }
+    None
+}
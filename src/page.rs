@@ -0,0 +1,174 @@
+// ============================================================================
+// VIEW SETTINGS
+// ============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::html_escape;
+use crate::source_view::source_view_html;
+use crate::TocEntry;
+
+/// Per-extension view preferences, persisted by the app and baked into each page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Settings {
+    pub window_width: f64,
+    pub window_height: f64,
+    pub toc_visible: bool,
+    pub view_mode: String,
+    pub font_size_level: i32,
+    pub theme: String,
+    #[serde(default)]
+    pub cells_collapsed: bool,
+    #[serde(default)]
+    pub output_wrapped: bool,
+    /// Prefix headings (and their TOC entries) with section numbers: 1, 1.1, 1.2.3
+    #[serde(default)]
+    pub number_headings: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            window_width: 800.0,
+            window_height: 900.0,
+            toc_visible: true,
+            view_mode: "github".to_string(),
+            font_size_level: 0,
+            theme: "dark".to_string(),
+            cells_collapsed: false,
+            output_wrapped: false,
+            number_headings: false,
+        }
+    }
+}
+
+// ============================================================================
+// HTML TEMPLATE BUILDING
+// ============================================================================
+
+// Frontend assets and vendored libraries, embedded so every page is self-contained
+const CSS: &str = include_str!("style.css");
+const JS: &str = include_str!("script.js");
+const HTML_TEMPLATE: &str = include_str!("template.html");
+const HLJS_JS: &str = include_str!("../vendor/highlight.min.js");
+const HLJS_CSS: &str = include_str!("../vendor/github-dark.min.css");
+const KATEX_JS: &str = include_str!("../vendor/katex.min.js");
+const KATEX_CSS: &str = include_str!("../vendor/katex-embedded.min.css");
+const KATEX_AUTO: &str = include_str!("../vendor/auto-render.min.js");
+const MERMAID_JS: &str = include_str!("../vendor/mermaid.min.js");
+
+pub fn build_settings_json(settings: &Settings, extension: &str) -> String {
+    let mut settings_with_ext = serde_json::to_value(settings).unwrap_or(serde_json::json!({}));
+    if let Some(obj) = settings_with_ext.as_object_mut() {
+        obj.insert("extension".to_string(), serde_json::json!(extension));
+    }
+    serde_json::to_string(&settings_with_ext).unwrap_or_else(|_| "{}".to_string())
+}
+
+/// Render TOC sidebar links that scroll to each heading's slug.
+/// `data-slug`/`data-line` let the JS reuse the Rust slugs (terminal view ids, TOC highlight).
+pub fn build_toc_html(toc: &[TocEntry]) -> String {
+    toc.iter()
+        .map(|entry| {
+            // Classes from `{.class}` heading attributes carry over with a `toc-` prefix
+            let classes: String = entry.classes.iter().map(|class| format!(" toc-{}", html_escape(class))).collect();
+            format!(
+                r##"<a href="#" onclick="scrollToHeading(this.dataset.slug); return false;" class="toc-item toc-level-{}{}" data-slug="{slug}" data-line="{}">{}</a>"##,
+                entry.level, classes, entry.line, html_escape(&entry.text), slug = html_escape(&entry.slug)
+            )
+        })
+        .collect()
+}
+
+/// Wrap a rendered markdown fragment in the full page (GitHub + terminal views).
+pub fn build_full_html_markdown(content: &str, rendered_html: &str, toc: &[TocEntry], settings: &Settings, extension: &str) -> String {
+    let settings_json = build_settings_json(settings, extension);
+    let toc_html = build_toc_html(toc);
+    let source_view = source_view_html(content, toc);
+
+    // Create JSON array of markdown lines for copy handler
+    let markdown_lines_json: String = content
+        .lines()
+        .map(|line| {
+            let escaped = line
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\t', "\\t");
+            format!("\"{}\"", escaped)
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // Determine initial view mode
+    let (github_display, terminal_display, initial_mode) = if settings.view_mode == "terminal" {
+        ("none", "block", "terminal")
+    } else {
+        ("block", "none", "github")
+    };
+
+    // Calculate initial font size and theme
+    let base_font_size: f64 = 15.0;
+    let scale = 1.0 + (settings.font_size_level as f64 * 0.1);
+    let font_size = format!("{:.1}", base_font_size * scale);
+    let body_class = if settings.theme == "light" { "light" } else { "" };
+
+    HTML_TEMPLATE
+        .replace("{hljs_css}", HLJS_CSS)
+        .replace("{hljs_js}", HLJS_JS)
+        .replace("{katex_css}", KATEX_CSS)
+        .replace("{katex_js}", KATEX_JS)
+        .replace("{katex_auto}", KATEX_AUTO)
+        .replace("{css}", CSS)
+        .replace("{body_class}", body_class)
+        .replace("{font_size}", &font_size)
+        .replace("{github_view}", rendered_html)
+        .replace("{terminal_view}", &source_view)
+        .replace("{notebook_view}", "")
+        .replace("{md_display}", "block")
+        .replace("{nb_display}", "none")
+        .replace("{github_display}", github_display)
+        .replace("{terminal_display}", terminal_display)
+        .replace("{initial_mode}", initial_mode)
+        .replace("{toc}", &toc_html)
+        .replace("{markdown_lines}", &markdown_lines_json)
+        .replace("{settings}", &settings_json)
+        .replace("{js}", JS)
+        // Last, so nothing inside the (large) mermaid bundle is mistaken for a placeholder
+        .replace("{mermaid_js}", MERMAID_JS)
+}
+
+/// Wrap a rendered notebook fragment in the full page.
+pub fn build_full_html_notebook(notebook_html: &str, toc: &[TocEntry], settings: &Settings, extension: &str) -> String {
+    let settings_json = build_settings_json(settings, extension);
+    let toc_html = build_toc_html(toc);
+
+    // Calculate initial font size and theme
+    let base_font_size: f64 = 15.0;
+    let scale = 1.0 + (settings.font_size_level as f64 * 0.1);
+    let font_size = format!("{:.1}", base_font_size * scale);
+    let body_class = if settings.theme == "light" { "light" } else { "" };
+
+    HTML_TEMPLATE
+        .replace("{hljs_css}", HLJS_CSS)
+        .replace("{hljs_js}", HLJS_JS)
+        .replace("{katex_css}", KATEX_CSS)
+        .replace("{katex_js}", KATEX_JS)
+        .replace("{katex_auto}", KATEX_AUTO)
+        .replace("{css}", CSS)
+        .replace("{body_class}", body_class)
+        .replace("{font_size}", &font_size)
+        .replace("{github_view}", "")
+        .replace("{terminal_view}", "")
+        .replace("{notebook_view}", notebook_html)
+        .replace("{md_display}", "none")
+        .replace("{nb_display}", "block")
+        .replace("{github_display}", "none")
+        .replace("{terminal_display}", "none")
+        .replace("{initial_mode}", "github")
+        .replace("{toc}", &toc_html)
+        .replace("{markdown_lines}", "")
+        .replace("{settings}", &settings_json)
+        .replace("{js}", JS)
+        // Last, so nothing inside the (large) mermaid bundle is mistaken for a placeholder
+        .replace("{mermaid_js}", MERMAID_JS)
+}
@@ -8,6 +8,7 @@ let fontSizeLevel = initialSettings.font_size_level || 0;
 let currentTheme = initialSettings.theme || 'dark';
 let cellsCollapsedPref = initialSettings.cells_collapsed || false;
 let outputWrapped = initialSettings.output_wrapped || false;
+let numberHeadings = initialSettings.number_headings || false;
 const currentExtension = initialSettings.extension || 'md';
 const isNotebook = currentExtension === 'ipynb';
 const TOC_WIDTH = 200;
@@ -31,7 +32,8 @@ function saveSettings() {
             font_size_level: fontSizeLevel,
             theme: currentTheme,
             cells_collapsed: cellsCollapsed,
-            output_wrapped: outputWrapped
+            output_wrapped: outputWrapped,
+            number_headings: numberHeadings
         };
         // Include extension in message format: save_settings:ext:{json}
         window.ipc.postMessage('save_settings:' + currentExtension + ':' + JSON.stringify(settings));
@@ -60,8 +62,10 @@ function applyFontSize() {
 }
 
 function setTheme(theme) {
+    const changed = theme !== currentTheme;
     currentTheme = theme;
     document.body.classList.toggle('light', theme === 'light');
+    if (changed) renderMermaid(document.getElementById('content'));
     saveSettings();
 }
 
@@ -69,6 +73,50 @@ function toggleTheme() {
     setTheme(currentTheme === 'dark' ? 'light' : 'dark');
 }
 
+// ============================================================================
+// HEADING NUMBERS
+// ============================================================================
+
+function toggleHeadingNumbers() {
+    numberHeadings = !numberHeadings;
+    applyHeadingNumbers();
+    saveSettings();
+}
+
+/**
+ * Number the TOC entries and their headings 1, 1.1, 1.2.3 (as data-number, which CSS shows
+ * while numbering is on). A lone top-level heading at the start is the document title and
+ * stays unnumbered; skipped levels count as 0, like pandoc.
+ */
+function applyHeadingNumbers() {
+    document.body.classList.toggle('numbered-headings', numberHeadings);
+    const items = Array.from(document.querySelectorAll('#toc .toc-item'));
+    const levels = items.map(item => Number((item.className.match(/toc-level-(\d)/) || [])[1]) || 1);
+    if (items.length === 0) return;
+
+    let top = Math.min(...levels);
+    if (levels[0] === top && levels.length > 1 && levels.filter(level => level === top).length === 1) {
+        top += 1;
+    }
+
+    const counters = [];
+    items.forEach((item, i) => {
+        const depth = levels[i] - top;
+        let number = null;
+        if (depth >= 0) {
+            counters.length = depth + 1;
+            for (let d = 0; d < depth; d++) counters[d] = counters[d] || 0;
+            counters[depth] = (counters[depth] || 0) + 1;
+            number = counters.join('.');
+        }
+        const headings = document.querySelectorAll('#github-view #' + CSS.escape(item.dataset.slug) + ', #notebook-view #' + CSS.escape(item.dataset.slug));
+        [item, ...headings].forEach(el => {
+            if (number) el.dataset.number = number;
+            else delete el.dataset.number;
+        });
+    });
+}
+
 // ============================================================================
 // VIEW SWITCHING & NAVIGATION
 // ============================================================================
@@ -83,7 +131,7 @@ function getActiveViewSelector() {
 function getCurrentHeadingId() {
     const content = document.getElementById('content');
     const activeView = getActiveViewSelector();
-    const headings = document.querySelectorAll(activeView + ' h1, ' + activeView + ' h2, ' + activeView + ' h3, ' + activeView + ' h4, ' + activeView + ' h5, ' + activeView + ' h6, ' + activeView + ' [id].md-heading');
+    const headings = document.querySelectorAll(activeView + ' h1[id], ' + activeView + ' h2[id], ' + activeView + ' h3[id], ' + activeView + ' h4[id], ' + activeView + ' h5[id], ' + activeView + ' h6[id], ' + activeView + ' [id].md-heading');
 
     let currentHeading = null;
     // Clamp scrollTop to valid range (handles macOS bounce effect)
@@ -196,6 +244,71 @@ function scrollToHeading(slug) {
     }
 }
 
+/**
+ * Scroll to a target given on the command line: {line}, {heading, slug} or {find}.
+ */
+function scrollToTarget(target) {
+    if (!target) return;
+
+    if (target.find) {
+        openSearch();
+        document.getElementById('search-input').value = target.find;
+        performSearch(target.find);
+        return;
+    }
+
+    let el = null;
+    if (target.heading) {
+        el = findHeading(target.heading, target.slug);
+    } else if (target.line && !isNotebook) {
+        // Notebook data-lines are relative to each cell, so lines only apply to markdown files
+        el = currentMode === 'terminal' ? findTerminalLine(target.line) : findSourceLine(target.line);
+    }
+    if (el) {
+        el.scrollIntoView({ block: 'start' });
+        el.classList.add('scroll-target');
+        setTimeout(() => el.classList.remove('scroll-target'), 1500);
+    }
+}
+
+function findHeading(text, slug) {
+    const activeView = getActiveViewSelector();
+    const byId = document.querySelector(activeView + ' #' + CSS.escape(slug)) ||
+        document.querySelector(activeView + ' #' + CSS.escape(text));
+    if (byId) return byId;
+
+    // Fall back to a case-insensitive match on the heading text
+    const wanted = text.trim().toLowerCase();
+    const headings = document.querySelectorAll(activeView + ' h1, ' + activeView + ' h2, ' + activeView + ' h3, ' + activeView + ' h4, ' + activeView + ' h5, ' + activeView + ' h6, ' + activeView + ' .md-heading');
+    return Array.from(headings).find(h => h.textContent.replace(/^#+\s*/, '').trim().toLowerCase() === wanted) || null;
+}
+
+function findSourceLine(line) {
+    // The last block containing the line is the innermost; otherwise take the next block after it
+    let containing = null;
+    let following = null;
+    document.querySelectorAll('#github-view [data-lines]').forEach(el => {
+        const [start, end] = el.dataset.lines.split('-').map(Number);
+        if (start <= line && line <= end) {
+            containing = el;
+        } else if (start > line && !following) {
+            following = el;
+        }
+    });
+    return containing || following;
+}
+
+function findTerminalLine(line) {
+    let current = 1;
+    for (const el of document.querySelectorAll('#terminal-view > .line')) {
+        // Code blocks are rendered as one element spanning several source lines
+        const span = el.classList.contains('md-code-block-wrapper') ? el.textContent.split('\n').length : 1;
+        if (line < current + span) return el;
+        current += span;
+    }
+    return null;
+}
+
 // ============================================================================
 // COPY HANDLING (Cmd+C)
 // ============================================================================
@@ -255,8 +368,11 @@ function extractMarkdownForSelection() {
             const markdownBlock = markdownLines.slice(minLine - 1, maxLine).join('\n');
             const selectedText = selection.toString();
             let extracted = markdownBlock;
+            // Text picked out of a rendered diagram can't be matched against its source
+            const inDiagram = Array.from(document.querySelectorAll('#github-view .mermaid'))
+                .some(el => selection.containsNode(el, true));
 
-            if (selectedText.trim()) {
+            if (selectedText.trim() && !inDiagram) {
                 const words = selectedText.trim().split(/\s+/);
                 const firstWords = words.slice(0, 3).join(' ');
                 const lastWords = words.slice(-3).join(' ');
@@ -448,6 +564,9 @@ document.addEventListener('keydown', function(e) {
         case 'd':
             toggleTheme();
             break;
+        case 'n':
+            toggleHeadingNumbers();
+            break;
     }
 });
 
@@ -724,6 +843,52 @@ function receiveOutputLines(cellIdx, outputIdx, linesHtml, hiddenRemaining, isCo
 // MARKDOWN: CODE BLOCKS & TERMINAL VIEW
 // ============================================================================
 
+// Wrap each line of a highlighted code block in a .code-line span for the fence's
+// {3-5} highlights (data-highlight) and line numbers (data-linenums). hljs spans that
+// cross a line break are closed and reopened; the text, and so copying, is unchanged.
+function decorateCodeLines(codeBlock) {
+    const pre = codeBlock.parentElement;
+    if (!pre || (!pre.dataset.highlight && !pre.dataset.linenums) || codeBlock.querySelector('.code-line')) return;
+
+    const highlighted = new Set();
+    (pre.dataset.highlight || '').split(',').filter(Boolean).forEach(range => {
+        const [start, end] = range.split('-').map(Number);
+        for (let line = start; line <= end; line++) highlighted.add(line);
+    });
+    const firstNumber = pre.dataset.linenums ? Number(pre.dataset.linenums) : null;
+
+    const lines = [];
+    const open = [];
+    let current = '';
+    const tokens = /(<span[^>]*>)|(<\/span>)|(\n)|([^<\n]+)/g;
+    let match;
+    while ((match = tokens.exec(codeBlock.innerHTML))) {
+        if (match[1]) {
+            open.push(match[1]);
+            current += match[1];
+        } else if (match[2]) {
+            open.pop();
+            current += match[2];
+        } else if (match[3]) {
+            lines.push(current + '</span>'.repeat(open.length));
+            current = open.join('');
+        } else {
+            current += match[4];
+        }
+    }
+    if (current !== open.join('')) lines.push(current + '</span>'.repeat(open.length));
+
+    codeBlock.innerHTML = lines.map((line, i) => {
+        const cls = highlighted.has(i + 1) ? 'code-line highlighted' : 'code-line';
+        const number = firstNumber !== null ? ` data-line-number="${firstNumber + i}"` : '';
+        return `<span class="${cls}"${number}>${line}\n</span>`;
+    }).join('');
+    if (firstNumber !== null) {
+        codeBlock.classList.add('numbered');
+        codeBlock.style.setProperty('--linenum-width', String(firstNumber + lines.length - 1).length + 'ch');
+    }
+}
+
 function initCodeBlocks() {
     // GitHub view: add language labels to code blocks
     document.querySelectorAll('#github-view pre code').forEach((codeBlock) => {
@@ -744,10 +909,11 @@ function initCodeBlocks() {
             pre.insertBefore(header, codeBlock);
         }
 
-        // Apply syntax highlighting
-        if (typeof hljs !== 'undefined') {
+        // Apply syntax highlighting (blocks with a grammar in Rust come highlighted)
+        if (typeof hljs !== 'undefined' && !codeBlock.classList.contains('hljs')) {
             hljs.highlightElement(codeBlock);
         }
+        decorateCodeLines(codeBlock);
     });
 
     // GitHub view: add click-to-expand for images
@@ -756,250 +922,110 @@ function initCodeBlocks() {
             expandFigure(this);
         });
     });
-
-    // Terminal view: custom markdown syntax highlighting
-    highlightMarkdown();
 }
 
-function formatTable(tableLines) {
-    // Parse table into cells
-    const rows = tableLines.map(line => {
-        const cells = line.split('|').slice(1, -1).map(c => c.trim());
-        return cells;
-    });
-
-    if (rows.length < 2) return tableLines;
+// ============================================================================
+// MATH
+// ============================================================================
 
-    // Find max width for each column
-    const colWidths = [];
-    for (const row of rows) {
-        for (let i = 0; i < row.length; i++) {
-            const cellLen = row[i].replace(/[-:]/g, m => m === '-' ? '-' : '').length;
-            colWidths[i] = Math.max(colWidths[i] || 0, cellLen, 3);
-        }
-    }
+// Typeset the math nodes from the renderer (.math-inline, .math-display and ```math
+// blocks) with KaTeX, using the document's macros. Notebook markdown cells are also
+// scanned for `$` delimiters the parser doesn't accept (such as `$ x $`).
+function renderMath(root) {
+    if (!root || typeof katex === 'undefined') return;
+    const macros = {};
+    root.querySelectorAll('.katex-macros').forEach(el => {
+        try {
+            Object.assign(macros, JSON.parse(el.dataset.macros));
+        } catch (e) {}
+    });
+    root.querySelectorAll('.math-inline, .math-display').forEach(el => {
+        if (el.dataset.tex === undefined) el.dataset.tex = el.textContent.trim();
+        katex.render(el.dataset.tex, el, {
+            displayMode: el.classList.contains('math-display'),
+            throwOnError: false,
+            macros: macros
+        });
+    });
 
-    // Format each row
-    return rows.map((row, rowIndex) => {
-        const cells = row.map((cell, i) => {
-            const width = colWidths[i] || 3;
-            if (rowIndex === 1 && cell.match(/^[-:]+$/)) {
-                // Separator row
-                return '-'.repeat(width);
-            }
-            return cell.padEnd(width);
+    if (typeof renderMathInElement === 'undefined') return;
+    root.querySelectorAll('.nb-markdown-cell').forEach(cell => {
+        renderMathInElement(cell, {
+            delimiters: [
+                {left: '$$', right: '$$', display: true},
+                {left: '$', right: '$', display: false}
+            ],
+            ignoredClasses: ['math', 'math-block'],
+            throwOnError: false,
+            macros: macros
         });
-        return '| ' + cells.join(' | ') + ' |';
     });
 }
 
-function highlightMarkdown() {
-    const terminalView = document.getElementById('terminal-view');
-    // Get raw text from initial content (stored in data attribute or parsed from escaped HTML)
-    let text = terminalView.textContent;
-    let html = '';
-    let inCodeBlock = false;
-    let codeBlockLang = '';
-    let codeBlockLines = [];
-    let inComment = false;
-
-    // Pre-process: format tables
-    let lines = text.split('\n');
-    let formattedLines = [];
-    let tableBuffer = [];
-
-    for (let i = 0; i < lines.length; i++) {
-        const line = lines[i];
-        if (line.trim().startsWith('|') && line.trim().endsWith('|')) {
-            tableBuffer.push(line);
-        } else {
-            if (tableBuffer.length > 0) {
-                formattedLines.push(...formatTable(tableBuffer));
-                tableBuffer = [];
-            }
-            formattedLines.push(line);
-        }
-    }
-    if (tableBuffer.length > 0) {
-        formattedLines.push(...formatTable(tableBuffer));
-    }
-
-    lines = formattedLines;
-
-    // Helper to create a line div with optional padding
-    function makeLine(content, indent) {
-        const style = indent > 0 ? ' style="padding-left:' + indent + 'ch"' : '';
-        return '<div class="line"' + style + '>' + content + '</div>';
-    }
-
-    for (let i = 0; i < lines.length; i++) {
-        let line = lines[i];
-
-        // HTML comments - handle multi-line (check before code blocks so comments containing code aren't highlighted)
-        if (inComment) {
-            if (line.includes('-->')) {
-                // Comment ends on this line
-                inComment = false;
-            }
-            html += makeLine('<span class="md-comment">' + escapeHtml(line) + '</span>', 0);
-            continue;
-        }
-
-        // Check for comment start (before code blocks)
-        if (line.includes('<!--')) {
-            if (!line.includes('-->')) {
-                // Comment starts but doesn't end on this line
-                inComment = true;
-            }
-            html += makeLine('<span class="md-comment">' + escapeHtml(line) + '</span>', 0);
-            continue;
-        }
-
-        // Code block start/end
-        if (line.match(/^```/)) {
-            if (!inCodeBlock) {
-                inCodeBlock = true;
-                codeBlockLang = line.slice(3).trim();
-                html += makeLine('<span class="md-code-fence">' + escapeHtml(line) + '</span>', 0);
-                codeBlockLines = [];
-            } else {
-                // End of code block - render collected content
-                if (codeBlockLines.length > 0) {
-                    let codeContent;
-                    if (codeBlockLang && typeof hljs !== 'undefined') {
-                        try {
-                            const highlighted = hljs.highlight(codeBlockLines.join('\n'), { language: codeBlockLang, ignoreIllegals: true });
-                            codeContent = highlighted.value;
-                        } catch (e) {
-                            codeContent = escapeHtml(codeBlockLines.join('\n'));
-                        }
-                    } else {
-                        codeContent = escapeHtml(codeBlockLines.join('\n'));
-                    }
-                    html += '<div class="line md-code-block-wrapper"><pre>' + codeContent + '</pre></div>';
-                }
-                html += makeLine('<span class="md-code-fence">' + escapeHtml(line) + '</span>', 0);
-                inCodeBlock = false;
-                codeBlockLang = '';
-            }
-            continue;
-        }
-
-        if (inCodeBlock) {
-            codeBlockLines.push(line);
-            continue;
-        }
-
-        // Check for indentation before escaping
-        const indentMatch = line.match(/^(\s+)/);
-        const indent = indentMatch ? indentMatch[1].length : 0;
-        const content = indent > 0 ? line.slice(indent) : line;
-
-        let processed = escapeHtml(content);
-
-        // Headings - add ID for navigation (use unescaped content for slug to match Rust)
-        if (content.match(/^#{1,6}\s/)) {
-            const headingText = content.replace(/^#+\s*/, '');
-            const slug = slugify(headingText);
-            html += makeLine('<span class="md-heading" id="' + slug + '">' + processed + '</span>', indent);
-            continue;
-        }
-
-        // Horizontal rules
-        if (processed.match(/^(-{3,}|\*{3,}|_{3,})$/)) {
-            html += makeLine('<span class="md-hr">' + processed + '</span>', indent);
-            continue;
-        }
-
-        // Blockquotes
-        if (processed.match(/^&gt;\s?/)) {
-            html += makeLine('<span class="md-blockquote">' + processed + '</span>', indent);
-            continue;
-        }
-
-        // Table rows
-        if (processed.match(/^\|.*\|$/)) {
-            if (processed.match(/^\|[\s\-|]+\|$/)) {
-                html += makeLine('<span class="md-table-sep">' + processed + '</span>', indent);
-            } else {
-                let tableLine = processed;
-                tableLine = tableLine.replace(/\*\*(.+?)\*\*/g, '<span class="md-bold">&#42;&#42;$1&#42;&#42;</span>');
-                tableLine = tableLine.replace(/__(.+?)__/g, '<span class="md-bold">&#95;&#95;$1&#95;&#95;</span>');
-                tableLine = tableLine.replace(/\*(.+?)\*/g, '<span class="md-italic">&#42;$1&#42;</span>');
-                tableLine = tableLine.replace(/(?<!\w)_(.+?)_(?!\w)/g, '<span class="md-italic">&#95;$1&#95;</span>');
-                tableLine = highlightInlineCode(tableLine);
-                tableLine = tableLine.replace(/\[([^\]]+)\]\(([^)]+)\)/g, '<a href="$2" class="md-link">[$1]($2)</a>');
-                html += makeLine('<span class="md-table">' + tableLine + '</span>', indent);
-            }
-            continue;
-        }
-
-        // List items
-        processed = processed.replace(/^([-*+]|\d+\.)\s/, '<span class="md-list-marker">$1</span> ');
-
-        // Inline formatting (use HTML entities for markers to prevent re-matching)
-        processed = processed.replace(/\*\*(.+?)\*\*/g, '<span class="md-bold">&#42;&#42;$1&#42;&#42;</span>');
-        processed = processed.replace(/__(.+?)__/g, '<span class="md-bold">&#95;&#95;$1&#95;&#95;</span>');
-        processed = processed.replace(/\*(.+?)\*/g, '<span class="md-italic">&#42;$1&#42;</span>');
-        processed = processed.replace(/(?<!\w)_(.+?)_(?!\w)/g, '<span class="md-italic">&#95;$1&#95;</span>');
-        processed = highlightInlineCode(processed);
-        processed = processed.replace(/\[([^\]]+)\]\(([^)]+)\)/g, '<a href="$2" class="md-link">[$1]($2)</a>');
+// ============================================================================
+// MERMAID DIAGRAMS
+// ============================================================================
 
-        html += makeLine(processed, indent);
-    }
+// Render ```mermaid blocks under root as SVG in the current theme. The source is kept
+// in data-source so diagrams can be redrawn when the theme changes.
+function renderMermaid(root) {
+    if (typeof mermaid === 'undefined' || !root) return;
+    const nodes = Array.from(root.querySelectorAll('.mermaid'));
+    if (nodes.length === 0) return;
 
-    terminalView.innerHTML = html;
-}
-
-function highlightInlineCode(text) {
-    // Parse backticks properly: count opening backticks, find matching closing sequence
-    let result = '';
-    let i = 0;
-    while (i < text.length) {
-        if (text[i] === '\u0060') {
-            // Count consecutive backticks
-            let backtickCount = 0;
-            let start = i;
-            while (i < text.length && text[i] === '\u0060') {
-                backtickCount++;
-                i++;
-            }
-            // Look for matching closing backticks
-            const closer = '\u0060'.repeat(backtickCount);
-            const closeIdx = text.indexOf(closer, i);
-            if (closeIdx !== -1) {
-                // Found matching closer
-                const codeContent = text.slice(i, closeIdx);
-                result += '<span class="md-code">' + closer + codeContent + closer + '</span>';
-                i = closeIdx + backtickCount;
-            } else {
-                // No matching closer, output backticks as-is
-                result += text.slice(start, i);
-            }
+    nodes.forEach(node => {
+        if (node.dataset.source === undefined) {
+            node.dataset.source = node.textContent;
         } else {
-            result += text[i];
-            i++;
+            node.textContent = node.dataset.source;
         }
-    }
-    return result;
+        node.removeAttribute('data-processed');
+    });
+    mermaid.initialize({
+        startOnLoad: false,
+        theme: currentTheme === 'light' ? 'default' : 'dark',
+        securityLevel: 'strict',
+    });
+    mermaid.run({ nodes: nodes, suppressErrors: true });
 }
 
-function escapeHtml(text) {
-    return text
-        .replace(/&/g, '&amp;')
-        .replace(/</g, '&lt;')
-        .replace(/>/g, '&gt;');
-}
+// ============================================================================
+// FOOTNOTE PREVIEW
+// ============================================================================
 
-function slugify(text) {
-    return text.toLowerCase()
-        .split('')
-        .map(c => /[a-z0-9]/.test(c) ? c : '-')
-        .join('')
-        .split('-')
-        .filter(s => s.length > 0)
-        .join('-');
-}
+let footnotePreview = null;
+
+// Delegated, so it keeps working after reloadContent() replaces the document
+document.addEventListener('mouseover', function(e) {
+    const ref = e.target.closest('.footnote-ref a');
+    if (!ref || footnotePreview) return;
+
+    // Footnote ids are per document (per cell in notebooks), so look next to the reference
+    const container = ref.closest('.nb-markdown-cell, #github-view');
+    const note = container && container.querySelector('#' + CSS.escape(ref.getAttribute('href').slice(1)));
+    if (!note) return;
+
+    footnotePreview = document.createElement('div');
+    footnotePreview.className = 'footnote-preview';
+    footnotePreview.innerHTML = note.innerHTML;
+    footnotePreview.querySelectorAll('.footnote-backref').forEach(a => a.remove());
+    document.body.appendChild(footnotePreview);
+
+    const rect = ref.getBoundingClientRect();
+    const left = Math.min(rect.left, window.innerWidth - footnotePreview.offsetWidth - 8);
+    const below = rect.bottom + 6;
+    footnotePreview.style.left = Math.max(8, left) + 'px';
+    footnotePreview.style.top = (below + footnotePreview.offsetHeight > window.innerHeight
+        ? rect.top - footnotePreview.offsetHeight - 6
+        : below) + 'px';
+});
+
+document.addEventListener('mouseout', function(e) {
+    if (footnotePreview && e.target.closest('.footnote-ref a')) {
+        footnotePreview.remove();
+        footnotePreview = null;
+    }
+});
 
 // ============================================================================
 // FILE RELOAD (Live refresh on file changes)
@@ -1019,6 +1045,14 @@ function reloadContent(newHtml, newTocHtml, isNotebookReload, newTerminalHtml) {
     const scrollTop = content.scrollTop;
     const scrollHeight = content.scrollHeight;
     const scrollRatio = scrollHeight > 0 ? scrollTop / scrollHeight : 0;
+    // Keep following the end of the document while it grows (e.g. streamed stdin)
+    const wasAtBottom = scrollTop + content.clientHeight >= scrollHeight - 5;
+
+    // Update TOC
+    const toc = document.getElementById('toc');
+    if (toc) {
+        toc.innerHTML = newTocHtml;
+    }
 
     // Update main content
     if (isNotebookReload) {
@@ -1027,54 +1061,34 @@ function reloadContent(newHtml, newTocHtml, isNotebookReload, newTerminalHtml) {
             notebookView.innerHTML = newHtml;
             // Re-initialize notebook features
             initNotebook();
-            // Re-render KaTeX math in markdown cells
-            if (typeof renderMathInElement !== 'undefined') {
-                notebookView.querySelectorAll('.nb-markdown-cell').forEach(cell => {
-                    renderMathInElement(cell, {
-                        delimiters: [
-                            {left: '$$', right: '$$', display: true},
-                            {left: '$', right: '$', display: false}
-                        ],
-                        throwOnError: false
-                    });
-                });
-            }
+            renderMermaid(notebookView);
+            renderMath(notebookView);
         }
     } else {
         const githubView = document.getElementById('github-view');
         const terminalView = document.getElementById('terminal-view');
         if (githubView) {
             githubView.innerHTML = newHtml;
-            // Re-render KaTeX math
-            if (typeof renderMathInElement !== 'undefined') {
-                renderMathInElement(githubView, {
-                    delimiters: [
-                        {left: '$$', right: '$$', display: true},
-                        {left: '$', right: '$', display: false}
-                    ],
-                    throwOnError: false
-                });
-            }
+            renderMath(githubView);
         }
         if (terminalView && newTerminalHtml) {
             terminalView.innerHTML = newTerminalHtml;
         }
         // Re-initialize markdown features
         initCodeBlocks();
+        renderMermaid(githubView);
     }
 
-    // Update TOC
-    const toc = document.getElementById('toc');
-    if (toc) {
-        toc.innerHTML = newTocHtml;
-    }
+    applyHeadingNumbers();
 
     // Restore scroll position (use ratio as fallback if content height changed significantly)
     requestAnimationFrame(() => {
         const newScrollHeight = content.scrollHeight;
         // If content height is similar, use absolute position; otherwise use ratio
         const oldHeightEstimate = scrollRatio > 0 ? scrollTop / scrollRatio : 0;
-        if (Math.abs(newScrollHeight - oldHeightEstimate) < 100 || scrollRatio === 0) {
+        if (wasAtBottom && scrollTop > 0) {
+            content.scrollTop = newScrollHeight;
+        } else if (Math.abs(newScrollHeight - oldHeightEstimate) < 100 || scrollRatio === 0) {
             content.scrollTop = scrollTop;
         } else {
             content.scrollTop = scrollRatio * newScrollHeight;
@@ -1092,7 +1106,7 @@ function reloadContent(newHtml, newTocHtml, isNotebookReload, newTerminalHtml) {
 function updateTocHighlight() {
     const content = document.getElementById('content');
     const activeView = getActiveViewSelector();
-    const headings = document.querySelectorAll(activeView + ' h1, ' + activeView + ' h2, ' + activeView + ' h3, ' + activeView + ' h4, ' + activeView + ' h5, ' + activeView + ' h6, ' + activeView + ' [id].md-heading');
+    const headings = document.querySelectorAll(activeView + ' h1[id], ' + activeView + ' h2[id], ' + activeView + ' h3[id], ' + activeView + ' h4[id], ' + activeView + ' h5[id], ' + activeView + ' h6[id], ' + activeView + ' [id].md-heading');
 
     let currentHeading = null;
     const scrollTop = content.scrollTop;
@@ -1109,7 +1123,7 @@ function updateTocHighlight() {
     document.querySelectorAll('.toc-item').forEach(item => item.classList.remove('active'));
 
     if (currentHeading && currentHeading.id) {
-        const tocItem = document.querySelector('.toc-item[onclick*="' + currentHeading.id + '"]');
+        const tocItem = document.querySelector('.toc-item[data-slug="' + CSS.escape(currentHeading.id) + '"]');
         if (tocItem) {
             tocItem.classList.add('active');
         }
@@ -1155,11 +1169,25 @@ document.addEventListener('DOMContentLoaded', function() {
         document.body.classList.add('light');
     }
 
+    // Typeset math, and draw diagrams once the theme is known
+    renderMath(document.getElementById('content'));
+    renderMermaid(document.getElementById('content'));
+
+    applyHeadingNumbers();
+
     // Initial highlight
     updateTocHighlight();
 
     // Reveal content after initialization (hidden in template to prevent flash)
     document.getElementById('content').style.visibility = '';
+
+    // Jump to --line / --heading / --find once layout has settled
+    if (window.initialScrollTarget) {
+        requestAnimationFrame(() => scrollToTarget(window.initialScrollTarget));
+    }
+
+    // Let the app know reloadContent() can be called (streamed stdin waits for this)
+    if (window.ipc) window.ipc.postMessage('ready');
 });
 
 function initNotebook() {
@@ -1168,9 +1196,10 @@ function initNotebook() {
 
     // Apply syntax highlighting to notebook code cells
     notebookView.querySelectorAll('pre code').forEach((codeBlock) => {
-        if (typeof hljs !== 'undefined') {
+        if (typeof hljs !== 'undefined' && !codeBlock.classList.contains('hljs')) {
             hljs.highlightElement(codeBlock);
         }
+        decorateCodeLines(codeBlock);
     });
 
     // Wire up collapse buttons
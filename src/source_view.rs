@@ -0,0 +1,306 @@
+// ============================================================================
+// SOURCE VIEW
+// ============================================================================
+
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::highlight::highlight_code_html;
+use crate::markdown::{code_language, parser_options};
+use crate::{html_escape, TocEntry};
+
+/// What a source line shows, decided from the block it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineKind {
+    Plain,
+    Comment,
+    Fence,
+    /// Inside a fenced code block (rendered with the block's first line)
+    Code,
+    Heading,
+    Rule,
+    Quote,
+    /// Row `.1` of table `.0`
+    TableRow(usize, usize),
+    TableSep(usize),
+}
+
+/// An inline element: the source range it covers and the tags around it.
+struct Mark {
+    range: Range<usize>,
+    open: String,
+    close: &'static str,
+}
+
+impl Mark {
+    fn span(range: Range<usize>, class: &str) -> Self {
+        Mark { range, open: format!(r#"<span class="{}">"#, class), close: "</span>" }
+    }
+}
+
+#[derive(Default)]
+struct Table {
+    alignments: Vec<Alignment>,
+    /// Cell ranges of each row, the header first
+    rows: Vec<Vec<Range<usize>>>,
+}
+
+/// The markdown source as shown in the terminal view: one `<div class="line">` per
+/// line (fenced code blocks are one `.md-code-block-wrapper` for all their lines),
+/// with comments, fences, headings, rules, quotes, tables, list markers and inline
+/// markup styled, and tables aligned. Headings get their ids from `toc`.
+pub fn source_view_html(markdown: &str, toc: &[TocEntry]) -> String {
+    let options = parser_options()
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS
+        | Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS;
+
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(markdown.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+    // Lines a block covers (its range ends after the newline of its last line)
+    let lines_of = |range: &Range<usize>| line_of(range.start)..=line_of(range.end.max(range.start + 1) - 1);
+
+    let mut kinds = vec![LineKind::Plain; line_starts.len()];
+    let mut marks: Vec<Mark> = Vec::new();
+    let mut tables: Vec<Table> = Vec::new();
+    // Highlighted code blocks by their first content line
+    let mut code_blocks: HashMap<usize, String> = HashMap::new();
+    let mut code: Option<(Option<String>, String)> = None;
+    let mut quote_depth = 0;
+
+    for (event, range) in Parser::new_ext(markdown, options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::BlockQuote(_)) => {
+                if quote_depth == 0 {
+                    for line in lines_of(&range) {
+                        kinds[line] = LineKind::Quote;
+                    }
+                }
+                quote_depth += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => quote_depth -= 1,
+            Event::Start(Tag::Heading { .. }) => {
+                for line in lines_of(&range) {
+                    kinds[line] = LineKind::Heading;
+                }
+            }
+            Event::Rule if quote_depth == 0 => kinds[line_of(range.start)] = LineKind::Rule,
+            Event::Start(Tag::MetadataBlock(_)) => {
+                for line in lines_of(&range) {
+                    kinds[line] = LineKind::Comment;
+                }
+            }
+            Event::Html(html) if quote_depth == 0 && html.trim_start().starts_with("<!--") => {
+                // The first line of a comment block; the rest follow as further Html events
+                let block = range.start..markdown[range.start..].find("-->").map_or(markdown.len(), |i| range.start + i + 3);
+                for line in lines_of(&block) {
+                    kinds[line] = LineKind::Comment;
+                }
+            }
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) if quote_depth == 0 => {
+                code = Some((code_language(&info).map(str::to_string), String::new()));
+                let lines = lines_of(&range);
+                kinds[*lines.start()] = LineKind::Fence;
+                // Unclosed fences run to the end of the document
+                let last = *lines.end();
+                let closing = markdown[line_starts[last]..].trim_start();
+                if last > *lines.start() && (closing.starts_with("```") || closing.starts_with("~~~")) {
+                    kinds[last] = LineKind::Fence;
+                }
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, source)) = code.as_mut() {
+                    source.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                let Some((lang, source)) = code.take() else { continue };
+                let source = source.strip_suffix('\n').unwrap_or(&source);
+                if source.is_empty() {
+                    continue;
+                }
+                let first = line_of(range.start) + 1;
+                kinds[first..first + source.split('\n').count()].fill(LineKind::Code);
+                let html = lang.as_deref()
+                    .and_then(|lang| highlight_code_html(source, lang))
+                    .unwrap_or_else(|| html_escape(source));
+                code_blocks.insert(first, html);
+            }
+            Event::Start(Tag::Table(alignments)) if quote_depth == 0 => {
+                tables.push(Table { alignments, rows: Vec::new() });
+            }
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) if quote_depth == 0 => {
+                let index = tables.len().wrapping_sub(1);
+                if let Some(table) = tables.last_mut() {
+                    let line = line_of(range.start);
+                    kinds[line] = LineKind::TableRow(index, table.rows.len());
+                    if table.rows.is_empty() {
+                        kinds[line + 1] = LineKind::TableSep(index);
+                    }
+                    table.rows.push(Vec::new());
+                }
+            }
+            Event::Start(Tag::TableCell) if quote_depth == 0 => {
+                if let Some(row) = tables.last_mut().and_then(|table| table.rows.last_mut()) {
+                    row.push(trim_cell(markdown, range));
+                }
+            }
+            Event::Start(Tag::Item) => {
+                let marker = markdown[range.start..].find(char::is_whitespace).unwrap_or(0);
+                if marker > 0 {
+                    marks.push(Mark::span(range.start..range.start + marker, "md-list-marker"));
+                }
+            }
+            Event::Start(Tag::Strong) => marks.push(Mark::span(range, "md-bold")),
+            Event::Start(Tag::Emphasis) => marks.push(Mark::span(range, "md-italic")),
+            Event::Code(_) => marks.push(Mark::span(range, "md-code")),
+            Event::InlineHtml(html) if html.starts_with("<!--") => marks.push(Mark::span(range, "md-comment")),
+            Event::Start(Tag::Link { dest_url, .. }) | Event::Start(Tag::Image { dest_url, .. }) => {
+                marks.push(Mark {
+                    range,
+                    open: format!(r#"<a href="{}" class="md-link">"#, html_escape(&dest_url)),
+                    close: "</a>",
+                });
+            }
+            _ => {}
+        }
+    }
+    // Outer elements first, so the spans nest
+    marks.sort_by(|a, b| a.range.start.cmp(&b.range.start).then(b.range.end.cmp(&a.range.end)));
+
+    let heading_ids: HashMap<usize, &str> = toc.iter().map(|entry| (entry.line, entry.slug.as_str())).collect();
+    let mut html = String::with_capacity(markdown.len() * 2);
+
+    for (index, &start) in line_starts.iter().enumerate() {
+        let end = line_starts.get(index + 1).map_or(markdown.len(), |next| next - 1);
+        let line = markdown[start..end].trim_end_matches('\r');
+        let indent = line.len() - line.trim_start().len();
+        let content = start + indent..start + line.len();
+
+        let (class, body, indent) = match kinds[index] {
+            LineKind::Code => {
+                if let Some(code) = code_blocks.get(&index) {
+                    html.push_str(&format!(r#"<div class="line md-code-block-wrapper"><pre>{}</pre></div>"#, code));
+                }
+                continue;
+            }
+            LineKind::Comment => ("md-comment", html_escape(line), 0),
+            LineKind::Fence => ("md-code-fence", html_escape(line), 0),
+            LineKind::Rule => ("md-hr", html_escape(line.trim()), indent),
+            LineKind::Quote => ("md-blockquote", render_marked(markdown, content, &marks), indent),
+            LineKind::TableRow(table, row) => ("md-table", table_row_html(markdown, &tables[table], row, &marks), indent),
+            LineKind::TableSep(table) => ("md-table-sep", html_escape(&table_separator(markdown, &tables[table])), indent),
+            LineKind::Heading => {
+                let id = heading_ids.get(&(index + 1))
+                    .map(|slug| format!(r#" id="{}""#, html_escape(slug)))
+                    .unwrap_or_default();
+                let body = render_marked(markdown, content, &marks);
+                push_line(&mut html, &format!(r#"<span class="md-heading"{}>{}</span>"#, id, body), indent);
+                continue;
+            }
+            LineKind::Plain => ("", render_marked(markdown, content, &marks), indent),
+        };
+        if class.is_empty() {
+            push_line(&mut html, &body, indent);
+        } else {
+            push_line(&mut html, &format!(r#"<span class="{}">{}</span>"#, class, body), indent);
+        }
+    }
+    html
+}
+
+fn push_line(html: &mut String, content: &str, indent: usize) {
+    if indent > 0 {
+        html.push_str(&format!(r#"<div class="line" style="padding-left:{}ch">{}</div>"#, indent, content));
+    } else {
+        html.push_str(&format!(r#"<div class="line">{}</div>"#, content));
+    }
+}
+
+/// `markdown[range]` escaped, with the marks that overlap it (clipped to the range).
+fn render_marked(markdown: &str, range: Range<usize>, marks: &[Mark]) -> String {
+    let mut html = String::new();
+    let mut pos = range.start;
+    let mut open: Vec<&Mark> = Vec::new();
+
+    for mark in marks.iter().filter(|mark| mark.range.start < range.end && mark.range.end > range.start) {
+        let start = mark.range.start.max(range.start);
+        while let Some(outer) = open.last() {
+            let end = outer.range.end.min(range.end);
+            if end > start {
+                break;
+            }
+            html.push_str(&html_escape(&markdown[pos..end]));
+            html.push_str(outer.close);
+            pos = end;
+            open.pop();
+        }
+        html.push_str(&html_escape(&markdown[pos..start]));
+        html.push_str(&mark.open);
+        pos = start;
+        open.push(mark);
+    }
+    while let Some(outer) = open.pop() {
+        let end = outer.range.end.min(range.end);
+        html.push_str(&html_escape(&markdown[pos..end]));
+        html.push_str(outer.close);
+        pos = end;
+    }
+    html.push_str(&html_escape(&markdown[pos..range.end]));
+    html
+}
+
+/// A cell's range without its surrounding spaces and pipes.
+fn trim_cell(markdown: &str, range: Range<usize>) -> Range<usize> {
+    let text = &markdown[range.clone()];
+    let trimmed = text.trim_start_matches(['|', ' ', '\t']);
+    let start = range.end - trimmed.len();
+    let trimmed = trimmed.trim_end_matches(['|', ' ', '\t', '\r', '\n']);
+    start..start + trimmed.len()
+}
+
+/// Width of each column: its widest cell, at least 3.
+fn column_widths(markdown: &str, table: &Table) -> Vec<usize> {
+    (0..table.alignments.len())
+        .map(|column| {
+            table.rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| markdown[cell.clone()].chars().count())
+                .fold(3, usize::max)
+        })
+        .collect()
+}
+
+/// A table row with its cells padded to the column widths.
+fn table_row_html(markdown: &str, table: &Table, row: usize, marks: &[Mark]) -> String {
+    let cells: Vec<String> = column_widths(markdown, table).into_iter()
+        .enumerate()
+        .map(|(column, width)| {
+            let Some(cell) = table.rows[row].get(column) else { return " ".repeat(width) };
+            let padding = width.saturating_sub(markdown[cell.clone()].chars().count());
+            let text = render_marked(markdown, cell.clone(), marks);
+            match table.alignments[column] {
+                Alignment::Right => format!("{}{}", " ".repeat(padding), text),
+                Alignment::Center => format!("{}{}{}", " ".repeat(padding / 2), text, " ".repeat(padding - padding / 2)),
+                Alignment::Left | Alignment::None => format!("{}{}", text, " ".repeat(padding)),
+            }
+        })
+        .collect();
+    format!("| {} |", cells.join(" | "))
+}
+
+/// The delimiter row, keeping each column's alignment colons.
+fn table_separator(markdown: &str, table: &Table) -> String {
+    let cells: Vec<String> = column_widths(markdown, table).into_iter()
+        .zip(&table.alignments)
+        .map(|(width, alignment)| match alignment {
+            Alignment::Center => format!(":{}:", "-".repeat(width - 2)),
+            Alignment::Right => format!("{}:", "-".repeat(width - 1)),
+            Alignment::Left => format!(":{}", "-".repeat(width - 1)),
+            Alignment::None => "-".repeat(width),
+        })
+        .collect();
+    format!("| {} |", cells.join(" | "))
+}
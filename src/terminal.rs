@@ -0,0 +1,543 @@
+// ============================================================================
+// ANSI TERMINAL RENDERING (marrow cat)
+// ============================================================================
+
+use pulldown_cmark::{Alignment, BlockQuoteKind, CodeBlockKind, Event, HeadingLevel, Parser, Tag, TagEnd};
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+use crate::ansi::strip_ansi_codes;
+use crate::highlight::{find_syntax, syntax_set};
+use crate::markdown::{code_language, parser_options, prepare_source};
+use crate::notebook::{CellOutput, Notebook};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const STRIKE: &str = "\x1b[9m";
+const H1: &str = "\x1b[1;4;35m";
+const H2: &str = "\x1b[1;36m";
+const H3: &str = "\x1b[1;33m";
+const CODE: &str = "\x1b[32m";
+const LINK: &str = "\x1b[4;34m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+
+const CODE_THEME: &str = "base16-ocean.dark";
+
+fn code_theme() -> &'static Theme {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    &THEMES.get_or_init(ThemeSet::load_defaults).themes[CODE_THEME]
+}
+
+/// Highlight source code with 24-bit colors. Every line ends with a reset so
+/// styles never leak into the next line's prefix. Unknown languages stay plain.
+pub fn highlight_code_ansi(code: &str, lang: &str) -> String {
+    let syntaxes = syntax_set();
+    let syntax = find_syntax(lang).unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, code_theme());
+    let mut result = String::new();
+
+    for line in LinesWithEndings::from(code) {
+        let text = line.trim_end_matches('\n');
+        match highlighter.highlight_line(line, syntaxes) {
+            Ok(ranges) => {
+                let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                result.push_str(escaped.trim_end_matches('\n'));
+                result.push_str(RESET);
+            }
+            Err(_) => result.push_str(text),
+        }
+        result.push('\n');
+    }
+    result
+}
+
+/// Visible width of a string that may contain ANSI escapes
+fn visible_width(s: &str) -> usize {
+    strip_ansi_codes(s).chars().count()
+}
+
+// Line prefix contributed by an enclosing block (blockquote bar, list indent).
+// `first` replaces `rest` on the first line only (list markers).
+struct Prefix {
+    first: Option<String>,
+    rest: String,
+}
+
+struct TableState {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    current_row: Vec<String>,
+}
+
+/// Streams markdown events into ANSI text, tracking nested prefixes and styles.
+struct AnsiWriter {
+    out: String,
+    width: usize,
+    prefixes: Vec<Prefix>,
+    styles: Vec<&'static str>,
+    at_line_start: bool,
+    pending_blank: bool,
+    list_numbers: Vec<Option<u64>>,
+    link_stack: Vec<(String, usize)>, // (url, text start offset in `out`)
+    code_block: Option<(String, String)>, // (lang, collected source)
+    table: Option<TableState>,
+    cell_saved_out: Option<String>,
+}
+
+impl AnsiWriter {
+    fn new(width: usize) -> Self {
+        Self {
+            out: String::new(),
+            width,
+            prefixes: Vec::new(),
+            styles: Vec::new(),
+            at_line_start: true,
+            pending_blank: false,
+            list_numbers: Vec::new(),
+            link_stack: Vec::new(),
+            code_block: None,
+            table: None,
+            cell_saved_out: None,
+        }
+    }
+
+    fn write_prefix(&mut self) {
+        for prefix in &mut self.prefixes {
+            let text = prefix.first.take().unwrap_or_else(|| prefix.rest.clone());
+            self.out.push_str(&text);
+        }
+        for style in &self.styles {
+            self.out.push_str(style);
+        }
+        self.at_line_start = false;
+    }
+
+    /// Start a block: emit the blank separator line owed by the previous block
+    fn begin_block(&mut self) {
+        if self.pending_blank && !self.out.is_empty() {
+            let blank: String = self.prefixes.iter().map(|p| p.rest.clone()).collect();
+            self.out.push_str(blank.trim_end());
+            self.out.push('\n');
+        }
+        self.pending_blank = false;
+    }
+
+    fn end_block(&mut self) {
+        self.end_line();
+        self.pending_blank = true;
+    }
+
+    fn end_line(&mut self) {
+        if !self.at_line_start {
+            if !self.styles.is_empty() {
+                self.out.push_str(RESET);
+            }
+            self.out.push('\n');
+            self.at_line_start = true;
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let mut lines = text.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            if !line.is_empty() {
+                if self.at_line_start {
+                    self.begin_block();
+                    self.write_prefix();
+                }
+                self.out.push_str(line);
+            }
+            if lines.peek().is_some() {
+                if self.at_line_start {
+                    self.begin_block();
+                    self.write_prefix();
+                }
+                self.end_line();
+            }
+        }
+    }
+
+    fn push_style(&mut self, style: &'static str) {
+        self.styles.push(style);
+        if !self.at_line_start {
+            self.out.push_str(style);
+        }
+    }
+
+    fn pop_style(&mut self) {
+        self.styles.pop();
+        if !self.at_line_start {
+            self.out.push_str(RESET);
+            for style in &self.styles {
+                self.out.push_str(style);
+            }
+        }
+    }
+
+    /// Write pre-styled lines (each already reset at its end) under the current prefixes
+    fn raw_lines(&mut self, text: &str) {
+        for line in text.lines() {
+            if self.at_line_start {
+                self.begin_block();
+                self.write_prefix();
+            }
+            self.out.push_str(line);
+            self.end_line();
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        if let Some((_, source)) = &mut self.code_block {
+            match event {
+                Event::Text(text) => source.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => self.finish_code_block(),
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(Tag::Paragraph) => {}
+            Event::End(TagEnd::Paragraph) => self.end_block(),
+
+            Event::Start(Tag::Heading { level, .. }) => {
+                let (marker, style) = match level {
+                    HeadingLevel::H1 => ("#", H1),
+                    HeadingLevel::H2 => ("##", H2),
+                    HeadingLevel::H3 => ("###", H3),
+                    HeadingLevel::H4 => ("####", BOLD),
+                    HeadingLevel::H5 => ("#####", BOLD),
+                    HeadingLevel::H6 => ("######", BOLD),
+                };
+                self.push_style(style);
+                self.text(&format!("{} ", marker));
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                self.pop_style();
+                self.end_block();
+            }
+
+            Event::Start(Tag::BlockQuote(kind)) => {
+                self.begin_block();
+                self.prefixes.push(Prefix { first: None, rest: format!("{}│{} ", DIM, RESET) });
+                // GitHub alerts (`> [!NOTE]`) get their title on the first line
+                if let Some(kind) = kind {
+                    let (title, color) = match kind {
+                        BlockQuoteKind::Note => ("Note", BLUE),
+                        BlockQuoteKind::Tip => ("Tip", GREEN),
+                        BlockQuoteKind::Important => ("Important", MAGENTA),
+                        BlockQuoteKind::Warning => ("Warning", YELLOW),
+                        BlockQuoteKind::Caution => ("Caution", RED),
+                    };
+                    self.push_style(BOLD);
+                    self.push_style(color);
+                    self.text(title);
+                    self.pop_style();
+                    self.pop_style();
+                    self.end_line();
+                }
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.prefixes.pop();
+                self.pending_blank = true;
+            }
+
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => code_language(&info).unwrap_or("").to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code_block = Some((lang, String::new()));
+            }
+
+            Event::Start(Tag::List(first)) => {
+                if self.list_numbers.is_empty() {
+                    self.begin_block();
+                } else {
+                    self.end_line();
+                }
+                self.list_numbers.push(first);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.list_numbers.pop();
+                if self.list_numbers.is_empty() {
+                    self.end_block();
+                }
+            }
+            Event::Start(Tag::Item) => {
+                self.end_line();
+                self.pending_blank = false;
+                let marker = match self.list_numbers.last_mut() {
+                    Some(Some(n)) => {
+                        let marker = format!("{}.", n);
+                        *n += 1;
+                        marker
+                    }
+                    _ => "•".to_string(),
+                };
+                let indent = " ".repeat(marker.chars().count() + 1);
+                self.prefixes.push(Prefix { first: Some(format!("{}{}{} ", YELLOW, marker, RESET)), rest: indent });
+            }
+            Event::End(TagEnd::Item) => {
+                self.end_line();
+                self.prefixes.pop();
+                self.pending_blank = false;
+            }
+
+            Event::Start(Tag::Table(alignments)) => {
+                self.begin_block();
+                self.table = Some(TableState { alignments, rows: Vec::new(), current_row: Vec::new() });
+            }
+            Event::End(TagEnd::Table) => self.finish_table(),
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => {}
+            Event::End(TagEnd::TableHead) | Event::End(TagEnd::TableRow) => {
+                if let Some(table) = &mut self.table {
+                    let row = std::mem::take(&mut table.current_row);
+                    table.rows.push(row);
+                }
+            }
+            Event::Start(Tag::TableCell) => {
+                // Collect the cell into its own buffer; the document is swapped back at the cell end
+                self.cell_saved_out = Some(std::mem::take(&mut self.out));
+                self.at_line_start = false;
+            }
+            Event::End(TagEnd::TableCell) => {
+                let cell = std::mem::replace(&mut self.out, self.cell_saved_out.take().unwrap_or_default());
+                self.at_line_start = true;
+                if let Some(table) = &mut self.table {
+                    table.current_row.push(cell.trim().to_string());
+                }
+            }
+
+            Event::Start(Tag::Emphasis) => self.push_style(ITALIC),
+            Event::Start(Tag::Strong) => self.push_style(BOLD),
+            Event::Start(Tag::Strikethrough) => self.push_style(STRIKE),
+            Event::End(TagEnd::Emphasis) | Event::End(TagEnd::Strong) | Event::End(TagEnd::Strikethrough) => self.pop_style(),
+
+            Event::Start(Tag::Link { dest_url, .. }) => {
+                self.text("");
+                self.link_stack.push((dest_url.to_string(), self.out.len()));
+                self.push_style(LINK);
+            }
+            Event::End(TagEnd::Link) => {
+                self.pop_style();
+                if let Some((url, start)) = self.link_stack.pop() {
+                    let shown = strip_ansi_codes(&self.out[start.min(self.out.len())..]);
+                    if shown.trim() != url && !url.starts_with('#') {
+                        self.push_style(DIM);
+                        self.text(&format!(" ({})", url));
+                        self.pop_style();
+                    }
+                }
+            }
+            Event::Start(Tag::Image { .. }) => {
+                self.push_style(DIM);
+                self.text("[image: ");
+            }
+            Event::End(TagEnd::Image) => {
+                self.text("]");
+                self.pop_style();
+            }
+
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                self.push_style(DIM);
+                self.text(&format!("[^{}]: ", name));
+                self.pop_style();
+            }
+            Event::End(TagEnd::FootnoteDefinition) => self.end_block(),
+            Event::FootnoteReference(name) => {
+                self.push_style(DIM);
+                self.text(&format!("[^{}]", name));
+                self.pop_style();
+            }
+
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                self.push_style(CODE);
+                self.text(&code);
+                self.pop_style();
+            }
+            Event::InlineMath(math) => {
+                self.push_style(CODE);
+                self.text(&format!("${}$", math));
+                self.pop_style();
+            }
+            Event::DisplayMath(math) => {
+                self.push_style(CODE);
+                self.text(&format!("$${}$$", math));
+                self.pop_style();
+            }
+            Event::Html(html) | Event::InlineHtml(html) => {
+                self.push_style(DIM);
+                self.text(&html);
+                self.pop_style();
+            }
+            Event::SoftBreak | Event::HardBreak => {
+                if self.table.is_some() {
+                    self.text(" ");
+                } else {
+                    self.end_line();
+                }
+            }
+            Event::Rule => {
+                self.begin_block();
+                let rule = "─".repeat(self.width.max(3));
+                self.raw_lines(&format!("{}{}{}", DIM, rule, RESET));
+                self.pending_blank = true;
+            }
+            Event::TaskListMarker(checked) => {
+                self.text(if checked { "☑ " } else { "☐ " });
+            }
+            _ => {}
+        }
+    }
+
+    fn finish_code_block(&mut self) {
+        let Some((lang, source)) = self.code_block.take() else { return };
+        self.begin_block();
+        self.raw_lines(&format!("{}```{}{}", DIM, lang, RESET));
+        self.raw_lines(&highlight_code_ansi(&source, &lang));
+        self.raw_lines(&format!("{}```{}", DIM, RESET));
+        self.pending_blank = true;
+    }
+
+    fn finish_table(&mut self) {
+        let Some(table) = self.table.take() else { return };
+        let columns = table.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let mut widths = vec![3; columns];
+        for row in &table.rows {
+            for (i, cell) in row.iter().enumerate() {
+                widths[i] = widths[i].max(visible_width(cell));
+            }
+        }
+
+        let separator = format!("{}│{}", DIM, RESET);
+        let mut lines = Vec::new();
+        for (row_idx, row) in table.rows.iter().enumerate() {
+            let cells: Vec<String> = (0..columns)
+                .map(|i| {
+                    let cell = row.get(i).map(String::as_str).unwrap_or("");
+                    let pad = widths[i] - visible_width(cell);
+                    let cell = if row_idx == 0 { format!("{}{}{}", BOLD, cell, RESET) } else { cell.to_string() };
+                    match table.alignments.get(i) {
+                        Some(Alignment::Right) => format!("{}{}", " ".repeat(pad), cell),
+                        Some(Alignment::Center) => format!("{}{}{}", " ".repeat(pad / 2), cell, " ".repeat(pad - pad / 2)),
+                        _ => format!("{}{}", cell, " ".repeat(pad)),
+                    }
+                })
+                .collect();
+            lines.push(format!(" {} ", cells.join(&format!(" {} ", separator))).trim_end().to_string());
+            if row_idx == 0 {
+                let rule: Vec<String> = widths.iter().map(|w| "─".repeat(w + 2)).collect();
+                lines.push(format!("{}{}{}", DIM, rule.join("┼"), RESET));
+            }
+        }
+        self.raw_lines(&lines.join("\n"));
+        self.pending_blank = true;
+    }
+
+    fn finish(mut self) -> String {
+        self.end_line();
+        self.out
+    }
+}
+
+/// Render markdown as styled ANSI text for a terminal `width` columns wide.
+pub fn markdown_to_ansi(markdown: &str, width: usize) -> String {
+    let source = prepare_source(markdown);
+    let mut writer = AnsiWriter::new(width);
+    for event in Parser::new_ext(&source, parser_options()) {
+        writer.event(event);
+    }
+    writer.finish()
+}
+
+/// Render a notebook as styled ANSI text. Code cells are highlighted in the kernel's
+/// language; tracebacks keep their own ANSI colors.
+pub fn notebook_to_ansi(notebook: &Notebook, width: usize) -> String {
+    let language = notebook.language();
+    let mut out = String::new();
+
+    for cell in &notebook.cells {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let source = cell.source.to_string();
+        match cell.cell_type.as_str() {
+            "markdown" => out.push_str(&markdown_to_ansi(&source, width)),
+            "code" => {
+                let exec_count = cell.execution_count.map(|n| n.to_string()).unwrap_or_else(|| " ".to_string());
+                out.push_str(&format!("{}In [{}]:{}\n", BLUE, exec_count, RESET));
+                out.push_str(&highlight_code_ansi(&source, language));
+                for output in &cell.outputs {
+                    out.push('\n');
+                    render_output_ansi(&mut out, output, &exec_count);
+                }
+            }
+            "raw" => {
+                out.push_str(&source);
+                if !source.ends_with('\n') {
+                    out.push('\n');
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+fn render_output_ansi(out: &mut String, output: &CellOutput, exec_count: &str) {
+    let push_text = |out: &mut String, text: &str, style: &str| {
+        for line in text.lines() {
+            out.push_str(&format!("{}{}{}\n", style, line, if style.is_empty() { "" } else { RESET }));
+        }
+    };
+
+    match output.output_type.as_str() {
+        "stream" => {
+            if let Some(text) = &output.text {
+                let style = if output.name.as_deref() == Some("stderr") { YELLOW } else { "" };
+                push_text(out, &text.to_string(), style);
+            }
+        }
+        "execute_result" | "display_data" => {
+            let Some(data) = &output.data else { return };
+            if output.output_type == "execute_result" {
+                out.push_str(&format!("{}Out[{}]:{}\n", RED, exec_count, RESET));
+            }
+            // Terminals can't show images or HTML; prefer the plain-text repr when there is one
+            if let Some(mime) = ["image/png", "image/jpeg", "image/svg+xml"].iter().find(|m| data.contains_key(**m)) {
+                push_text(out, &format!("[{} output]", mime), DIM);
+            } else if let Some(text) = data.get("text/plain") {
+                push_text(out, &text.to_string(), "");
+            } else if data.contains_key("text/html") {
+                push_text(out, "[text/html output]", DIM);
+            }
+        }
+        "error" => match &output.traceback {
+            Some(traceback) if !traceback.is_empty() => {
+                for entry in traceback {
+                    for line in entry.lines() {
+                        out.push_str(line);
+                        out.push_str(RESET);
+                        out.push('\n');
+                    }
+                }
+            }
+            _ => {
+                let ename = output.ename.as_deref().unwrap_or("Error");
+                let evalue = output.evalue.as_deref().unwrap_or("");
+                push_text(out, &format!("{}: {}", ename, evalue), RED);
+            }
+        },
+        _ => {}
+    }
+}
@@ -0,0 +1,63 @@
+use marrow::{render_markdown, RenderOptions};
+use std::path::{Path, PathBuf};
+
+/// A fresh directory under the system temp dir holding `files`.
+fn fixture(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("marrow-includes-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    for (file, content) in files {
+        let path = dir.join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    dir
+}
+
+fn render_file(path: &Path) -> marrow::Rendered {
+    let options = RenderOptions {
+        base_dir: path.parent().map(Path::to_path_buf),
+        file: Some(path.to_path_buf()),
+        ..RenderOptions::default()
+    };
+    render_markdown(&std::fs::read_to_string(path).unwrap(), &options)
+}
+
+#[test]
+fn self_include_is_a_cycle() {
+    let dir = fixture("self", &[("a.md", "# A\n\n<!-- include: a.md -->\n")]);
+    let rendered = render_file(&dir.join("a.md"));
+
+    let slugs: Vec<&str> = rendered.toc.iter().map(|entry| entry.slug.as_str()).collect();
+    assert_eq!(slugs, ["a"]);
+    assert!(rendered.html.contains("Cannot include a.md: it would include itself"));
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn include_back_to_the_document_is_a_cycle() {
+    let dir = fixture("ab", &[
+        ("a.md", "# A\n\n<!-- include: b.md -->\n"),
+        ("b.md", "# B\n\n<!-- include: a.md -->\n"),
+    ]);
+    let rendered = render_file(&dir.join("a.md"));
+
+    let slugs: Vec<&str> = rendered.toc.iter().map(|entry| entry.slug.as_str()).collect();
+    assert_eq!(slugs, ["a", "b"]);
+    assert!(rendered.html.contains("Cannot include a.md: it would include itself"));
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn include_outside_the_document_folder_is_refused() {
+    let dir = fixture("outside", &[
+        ("secret.md", "# Secret\n"),
+        ("docs/a.md", "# A\n\n<!-- include: ../secret.md -->\n"),
+    ]);
+    let rendered = render_file(&dir.join("docs/a.md"));
+
+    let slugs: Vec<&str> = rendered.toc.iter().map(|entry| entry.slug.as_str()).collect();
+    assert_eq!(slugs, ["a"]);
+    assert!(rendered.html.contains("Cannot include ../secret.md: it is outside the document"));
+    let _ = std::fs::remove_dir_all(dir);
+}